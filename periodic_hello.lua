@@ -3,10 +3,5 @@ while true do
     print "Hello, world!"
     sleep(5)
 
-    local res, err = get_date()
-    if res then
-        print(res.stdout:match("(.-)%s*$"))
-    else
-        print("error", err)
-    end
+    print(time.format(time.now(), "%a %b %e %H:%M:%S %Z %Y", "local"))
 end
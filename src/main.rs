@@ -1,20 +1,87 @@
 use argh::FromArgs;
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
 #[derive(Debug, FromArgs)]
 #[argh(description = "Prototype for running multiple Lua coroutines")]
 struct Args {
+    #[argh(
+        option,
+        description = "path of the server's Unix socket (defaults to $UBER_DRIVER_SOCKET or /tmp/uber-driver.sock)"
+    )]
+    socket: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "URL of a server listening on TCP, e.g. https://host:7878, used instead of the socket"
+    )]
+    connect: Option<String>,
+    #[argh(
+        option,
+        description = "PEM file of CA certificates trusted to sign the server's certificate"
+    )]
+    tls_ca: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "PEM file with a client certificate to present to the server"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[argh(option, description = "PEM file with the key for --tls-cert")]
+    tls_key: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "name to verify the server's certificate against instead of the URL host"
+    )]
+    tls_domain: Option<String>,
     #[argh(subcommand)]
     command: Command,
 }
 
+// parsed once at startup, so the size of the serve options does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, FromArgs)]
 #[argh(subcommand)]
 enum Command {
+    Events(EventsCommand),
+    List(ListCommand),
     Log(LogCommand),
+    LogLevel(LogLevelCommand),
     Serve(ServeCommand),
     Start(StartCommand),
     Stop(StopCommand),
+    Wait(WaitCommand),
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "events",
+    description = "listen for driver lifecycle events from a server"
+)]
+struct EventsCommand {
+    #[argh(
+        positional,
+        description = "only report events for drivers with these identifiers or names, or matching these label selectors"
+    )]
+    driver_ids: Vec<String>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "list",
+    description = "list the drivers known to a server"
+)]
+struct ListCommand {
+    #[argh(
+        positional,
+        description = "only list drivers with this identifier or name, or matching a label selector such as app=web"
+    )]
+    selector: Option<String>,
+    #[argh(switch, description = "print the drivers as JSON instead of a table")]
+    json: bool,
 }
 
 #[derive(Debug, FromArgs)]
@@ -23,7 +90,63 @@ enum Command {
     name = "log",
     description = "listen for log messages from a server"
 )]
-struct LogCommand {}
+struct LogCommand {
+    #[argh(
+        option,
+        description = "least severe level to show: error, warn, info, debug or trace"
+    )]
+    level: Option<log::Level>,
+    #[argh(
+        option,
+        description = "only show records from this target or those nested below it (may be repeated)"
+    )]
+    target: Vec<String>,
+    #[argh(
+        option,
+        description = "only show records about drivers with this identifier or name, or matching this label selector (may be repeated)"
+    )]
+    driver: Vec<String>,
+    #[argh(
+        option,
+        description = "only show records whose message matches this regular expression"
+    )]
+    grep: Option<String>,
+    #[argh(
+        option,
+        description = "only show records whose whole message matches this glob"
+    )]
+    glob: Option<String>,
+    #[argh(
+        option,
+        description = "first show up to this many recent records kept by the server, like tail -n"
+    )]
+    replay: Option<u32>,
+    #[argh(
+        option,
+        from_str_fn(parse_since),
+        description = "only replay records since this RFC 3339 time or duration ago, e.g. 10m"
+    )]
+    since: Option<SystemTime>,
+    #[argh(
+        option,
+        default = "uber_client::LogOverflow::DropOldest",
+        description = "when falling too far behind: drop-oldest or disconnect"
+    )]
+    overflow: uber_client::LogOverflow,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "log-level",
+    description = "change the least severe level a running Lua script logs, by identifier or name, or for every script matching a label selector"
+)]
+struct LogLevelCommand {
+    #[argh(positional)]
+    driver_id: String,
+    #[argh(positional, description = "error, warn, info, debug or trace")]
+    level: log::Level,
+}
 
 #[derive(Debug, FromArgs)]
 #[argh(
@@ -31,7 +154,101 @@ struct LogCommand {}
     name = "serve",
     description = "start a server that runs Lua coroutines"
 )]
-struct ServeCommand {}
+struct ServeCommand {
+    #[argh(option, description = "TOML file to read server settings from")]
+    config: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "directory where drivers are recorded so they are relaunched after a restart"
+    )]
+    state_dir: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "permission bits for the socket file, in octal (e.g. 660)"
+    )]
+    socket_mode: Option<String>,
+    #[argh(option, description = "user that owns the socket file, by name or id")]
+    socket_owner: Option<String>,
+    #[argh(option, description = "group that owns the socket file, by name or id")]
+    socket_group: Option<String>,
+    #[argh(
+        option,
+        description = "instructions a driver may run between yields before it is preempted (0 for no limit)"
+    )]
+    instruction_budget: Option<u32>,
+    #[argh(
+        option,
+        description = "default bytes of Lua memory each driver may hold (0 for no limit)"
+    )]
+    memory_limit: Option<usize>,
+    #[argh(
+        option,
+        description = "program drivers may run with exec (may be repeated)"
+    )]
+    allow_exec: Vec<String>,
+    #[argh(
+        option,
+        description = "environment variable drivers may set for programs they exec (may be repeated)"
+    )]
+    allow_exec_env: Vec<String>,
+    #[argh(
+        option,
+        description = "sandbox profile drivers may request (may be repeated; default minimal and standard)"
+    )]
+    allow_sandbox: Vec<String>,
+    #[argh(
+        option,
+        description = "also listen for TLS connections on this TCP address, e.g. 0.0.0.0:7878"
+    )]
+    listen: Option<SocketAddr>,
+    #[argh(
+        option,
+        description = "PEM file with the certificate chain for the TCP listener"
+    )]
+    cert: Option<PathBuf>,
+    #[argh(option, description = "PEM file with the key for --cert")]
+    key: Option<PathBuf>,
+    #[argh(
+        option,
+        description = "PEM file of CA certificates; TCP clients must present a certificate they issued"
+    )]
+    client_ca: Option<PathBuf>,
+    #[argh(
+        switch,
+        description = "let TCP clients connect without a certificate when --client-ca is not given"
+    )]
+    allow_anonymous: bool,
+    #[argh(
+        option,
+        description = "uid:N, gid:N, user:NAME or group:NAME allowed to start drivers (may be repeated)"
+    )]
+    allow_start: Vec<String>,
+    #[argh(
+        option,
+        description = "uid:N, gid:N, user:NAME or group:NAME allowed to stop drivers (may be repeated)"
+    )]
+    allow_stop: Vec<String>,
+    #[argh(
+        option,
+        description = "uid:N, gid:N, user:NAME or group:NAME allowed to list drivers and follow logs and events (may be repeated)"
+    )]
+    allow_observe: Vec<String>,
+    #[argh(
+        option,
+        description = "seconds a terminated driver and its return values are kept (default 3600)"
+    )]
+    retention: Option<f64>,
+    #[argh(
+        option,
+        description = "number of recent log records kept for log --replay (default 1000)"
+    )]
+    log_history: Option<usize>,
+    #[argh(
+        option,
+        description = "number of log records or driver events a client may fall behind (default 1024)"
+    )]
+    log_queue: Option<usize>,
+}
 
 #[derive(Debug, FromArgs)]
 #[argh(
@@ -42,17 +259,125 @@ struct ServeCommand {}
 struct StartCommand {
     #[argh(positional)]
     path: PathBuf,
+    #[argh(
+        option,
+        description = "bytes of Lua memory the driver may hold, at most the server's limit"
+    )]
+    memory_limit: Option<u64>,
+    #[argh(
+        option,
+        description = "sandbox profile for the script: minimal, standard or trusted, if the server allows it"
+    )]
+    sandbox: Option<String>,
+    #[argh(
+        option,
+        default = "uber_client::RestartPolicy::Never",
+        description = "when to launch the script again: never, on-failure or always"
+    )]
+    restart: uber_client::RestartPolicy,
+    #[argh(
+        option,
+        description = "restarts allowed before the script is left terminated (default unlimited)"
+    )]
+    max_restarts: Option<u32>,
+    #[argh(
+        option,
+        from_str_fn(parse_seconds),
+        description = "seconds to wait before the first restart, doubling after each one"
+    )]
+    restart_delay: Option<Duration>,
+    #[argh(
+        option,
+        description = "argument for the script as key=value for a string or key:=json for any JSON value (may be repeated)"
+    )]
+    arg: Vec<String>,
+    #[argh(
+        option,
+        description = "JSON object of arguments for the script, which --arg adds to"
+    )]
+    args_json: Option<String>,
+    #[argh(
+        option,
+        description = "name for the driver, unique among running drivers (defaults to the file name and part of the identifier)"
+    )]
+    name: Option<String>,
+    #[argh(
+        option,
+        from_str_fn(parse_label),
+        description = "label for the driver as key=value (may be repeated)"
+    )]
+    label: Vec<(String, String)>,
+    #[argh(
+        option,
+        description = "identifier for the driver (defaults to a random UUID)"
+    )]
+    id: Option<String>,
+    #[argh(
+        switch,
+        description = "kill any driver with the same identifier, or running under the same name, instead of failing"
+    )]
+    replace: bool,
+    #[argh(
+        option,
+        description = "least severe level the script's log functions emit: error, warn, info (default), debug or trace"
+    )]
+    log_level: Option<log::Level>,
 }
 
 #[derive(Debug, FromArgs)]
 #[argh(
     subcommand,
     name = "stop",
-    description = "stop a Lua script with the given identifier"
+    description = "stop a Lua script by identifier or name, or every script matching a label selector"
 )]
 struct StopCommand {
     #[argh(positional)]
     driver_id: String,
+    #[argh(
+        option,
+        from_str_fn(parse_seconds),
+        description = "seconds the script may spend cleaning up before it is killed"
+    )]
+    grace: Option<Duration>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "wait",
+    description = "wait for a Lua script, by identifier or name, to terminate and print its return values",
+    note = "Exits with 0 if the script finished, 1 if it failed and 2 if it was killed."
+)]
+struct WaitCommand {
+    #[argh(positional)]
+    driver_id: String,
+}
+
+/// Parses a non-negative number of seconds.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("expected a non-negative number of seconds: {value}"))
+}
+
+fn parse_since(value: &str) -> Result<SystemTime, String> {
+    uber_client::parse_since(value)
+        .map_err(|_| format!("expected an RFC 3339 time or a duration: {value}"))
+}
+
+fn parse_label(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value: {value}"))
+}
+
+/// Reports options that are only wrong in combination, the way argh reports a bad value.
+fn usage_error(message: &str) -> ! {
+    eprintln!("{message}\n\nRun uber-driver --help for more information.");
+    std::process::exit(1)
 }
 
 #[tokio::main]
@@ -60,10 +385,188 @@ async fn main() {
     let args: Args = argh::from_env();
     log::debug!("{args:?}");
 
+    let endpoint = match args.connect {
+        Some(url) => {
+            let identity = match (args.tls_cert, args.tls_key) {
+                (Some(cert), Some(key)) => Some((cert, key)),
+                (None, None) => None,
+                _ => usage_error("--tls-cert and --tls-key must be given together"),
+            };
+            let tls = uber_client::TlsOptions {
+                ca: args.tls_ca,
+                identity,
+                domain: args.tls_domain,
+            };
+
+            uber_client::Endpoint::Tcp { url, tls }
+        }
+        None => uber_client::Endpoint::unix(args.socket.clone()),
+    };
+
     match args.command {
-        Command::Log(_arg) => uber_client::listen().await.unwrap(),
-        Command::Serve(_arg) => uber_server::serve().await.unwrap(),
-        Command::Start(arg) => uber_client::start(arg.path.as_path()).await.unwrap(),
-        Command::Stop(arg) => uber_client::stop(arg.driver_id).await.unwrap(),
+        Command::Events(arg) => uber_client::events(&endpoint, arg.driver_ids)
+            .await
+            .unwrap(),
+        Command::List(arg) => uber_client::list(&endpoint, arg.selector, arg.json)
+            .await
+            .unwrap(),
+        Command::Log(arg) => {
+            let options = uber_client::LogOptions {
+                level: arg.level,
+                targets: arg.target,
+                drivers: arg.driver,
+                grep: arg.grep,
+                glob: arg.glob,
+                replay: arg.replay,
+                since: arg.since,
+                overflow: arg.overflow,
+            };
+
+            uber_client::listen(&endpoint, options).await.unwrap()
+        }
+        Command::LogLevel(arg) => uber_client::set_log_level(&endpoint, arg.driver_id, arg.level)
+            .await
+            .unwrap(),
+        Command::Serve(arg) => {
+            // settings are layered: defaults, then the config file, the environment and flags
+            let mut config = match arg.config {
+                Some(path) => uber_server::Config::from_file(&path).unwrap(),
+                None => uber_server::Config::default(),
+            };
+
+            config.apply_env();
+
+            if arg.state_dir.is_some() {
+                config.state_dir = arg.state_dir;
+            }
+
+            if let Some(socket) = args.socket {
+                config.socket.path = socket;
+            }
+
+            if let Some(mode) = arg.socket_mode {
+                config.socket.mode = Some(uber_server::parse_mode(&mode).unwrap());
+            }
+
+            if arg.socket_owner.is_some() {
+                config.socket.owner = arg.socket_owner;
+            }
+
+            if arg.socket_group.is_some() {
+                config.socket.group = arg.socket_group;
+            }
+
+            if let Some(retention) = arg.retention {
+                config.retention = uber_server::parse_seconds(retention).unwrap();
+            }
+
+            if let Some(log_history) = arg.log_history {
+                config.log_history = log_history;
+            }
+
+            if let Some(log_queue) = arg.log_queue {
+                config.log_queue = log_queue;
+            }
+
+            if let Some(instruction_budget) = arg.instruction_budget {
+                config.instruction_budget = instruction_budget;
+            }
+
+            if let Some(memory_limit) = arg.memory_limit {
+                config.memory_limit = memory_limit;
+            }
+
+            if !arg.allow_exec.is_empty() {
+                config.exec.allowlist = arg.allow_exec;
+            }
+
+            if !arg.allow_exec_env.is_empty() {
+                config.exec.env = arg.allow_exec_env;
+            }
+
+            if !arg.allow_sandbox.is_empty() {
+                config.sandboxes = arg
+                    .allow_sandbox
+                    .iter()
+                    .map(|profile| profile.parse().unwrap())
+                    .collect();
+            }
+
+            if !arg.allow_start.is_empty()
+                || !arg.allow_stop.is_empty()
+                || !arg.allow_observe.is_empty()
+            {
+                let parse = |principals: Vec<String>| -> Vec<uber_server::Principal> {
+                    principals
+                        .iter()
+                        .map(|principal| principal.parse().unwrap())
+                        .collect()
+                };
+
+                config.access = Some(uber_server::AccessPolicy {
+                    start: parse(arg.allow_start),
+                    stop: parse(arg.allow_stop),
+                    observe: parse(arg.allow_observe),
+                });
+            }
+
+            if let Some(address) = arg.listen {
+                let (cert, key) = match (arg.cert, arg.key) {
+                    (Some(cert), Some(key)) => (cert, key),
+                    _ => usage_error("--listen requires --cert and --key"),
+                };
+
+                config.tcp = Some(uber_server::TcpConfig {
+                    address,
+                    cert,
+                    key,
+                    client_ca: arg.client_ca,
+                    allow_anonymous: arg.allow_anonymous,
+                });
+            }
+
+            uber_server::serve(config).await.unwrap()
+        }
+        Command::Start(arg) => {
+            let mut args = match arg.args_json {
+                Some(json) => uber_client::Arguments::from_json(&json).unwrap(),
+                None => uber_client::Arguments::default(),
+            };
+
+            for assignment in &arg.arg {
+                args.set(assignment).unwrap();
+            }
+
+            let options = uber_client::StartOptions {
+                memory_limit: arg.memory_limit,
+                sandbox: arg.sandbox,
+                restart: arg.restart,
+                max_restarts: arg.max_restarts,
+                restart_delay: arg.restart_delay,
+                args,
+                name: arg.name,
+                labels: arg.label.into_iter().collect(),
+                driver_id: arg.id,
+                replace: arg.replace,
+                log_level: arg.log_level,
+            };
+
+            uber_client::start(&endpoint, arg.path.as_path(), options)
+                .await
+                .unwrap()
+        }
+        Command::Stop(arg) => uber_client::stop(&endpoint, arg.driver_id, arg.grace)
+            .await
+            .unwrap(),
+        Command::Wait(arg) => {
+            let status = uber_client::wait(&endpoint, arg.driver_id).await.unwrap();
+            let code = match status {
+                uber_client::DriverStatus::Finished => 0,
+                uber_client::DriverStatus::Killed => 2,
+                _ => 1,
+            };
+
+            std::process::exit(code)
+        }
     }
 }
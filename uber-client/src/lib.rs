@@ -1,32 +1,218 @@
 use std::{
-    path::Path,
-    time::{Duration, Instant},
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 use thiserror::Error;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::UnixStream,
-};
+use tokio::{io::AsyncReadExt, net::UnixStream};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
 use tower::service_fn;
 use uber_protos::{
-    driver_client::DriverClient, EchoRequest, StartDriverRequest, StopDriverRequest,
+    driver_client::DriverClient, driver_event::Event, DriverEventsRequest, DriverInfo, EchoRequest,
+    GetDriverRequest, ListDriversRequest, LogEvent, LogEventsRequest, LogLevel, SetLogLevelRequest,
+    StartDriverRequest, StopDriverRequest,
 };
 
-const UDS_PATH: &str = "/tmp/uber-driver.sock";
+pub use uber_protos::{DriverStatus, LogOverflow, RestartPolicy};
+
+/// Where the server listens unless told otherwise.
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/uber-driver.sock";
+
+/// Environment variable naming the server's socket, shared with the server.
+pub const SOCKET_PATH_ENV: &str = "UBER_DRIVER_SOCKET";
+
+/// tonic needs a URI for every channel, but the connector ignores it in favour of the socket path.
 const UDS_URI: &str = "http://tmp/uber-driver.sock";
 
 #[derive(Debug, Error)]
 pub enum UberClientError {
+    #[error("invalid server URL")]
+    InvalidUri(#[from] tonic::codegen::http::uri::InvalidUri),
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
     #[error("Lua error")]
     LuaError(#[from] mlua::Error),
     #[error("tonic request status")]
     Status(#[from] tonic::Status),
+    #[error("request rejected: {0}")]
+    Rejected(String),
     #[error("tonic transport error")]
     TransportError(#[from] tonic::transport::Error),
 }
 
+/// A driver argument that could not be parsed.
+#[derive(Debug, Error)]
+#[error("invalid driver argument: {0}")]
+pub struct InvalidArgument(String);
+
+/// The server a command talks to.
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    /// A server on this host, reached through its Unix socket.
+    Unix(PathBuf),
+    /// A server listening on TCP, such as `https://host:7878`.
+    Tcp { url: String, tls: TlsOptions },
+}
+
+impl Endpoint {
+    /// Uses `socket` if given, otherwise the path from the environment or the default.
+    pub fn unix(socket: Option<PathBuf>) -> Self {
+        let socket = socket
+            .or_else(|| std::env::var_os(SOCKET_PATH_ENV).map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH));
+
+        Endpoint::Unix(socket)
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::unix(None)
+    }
+}
+
+/// How an `https` connection is secured.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// PEM file of CA certificates trusted to sign the server's certificate.
+    pub ca: Option<PathBuf>,
+    /// PEM files holding a client certificate and its key, for servers that require one.
+    pub identity: Option<(PathBuf, PathBuf)>,
+    /// Name to verify the server's certificate against instead of the host in the URL.
+    pub domain: Option<String>,
+}
+
+impl TlsOptions {
+    fn config(&self) -> std::io::Result<ClientTlsConfig> {
+        let mut tls = ClientTlsConfig::new();
+
+        if let Some(ca) = &self.ca {
+            tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+        }
+
+        if let Some((cert, key)) = &self.identity {
+            tls = tls.identity(Identity::from_pem(
+                std::fs::read(cert)?,
+                std::fs::read(key)?,
+            ));
+        }
+
+        if let Some(domain) = &self.domain {
+            tls = tls.domain_name(domain);
+        }
+
+        Ok(tls)
+    }
+}
+
+/// Optional settings for a driver started with [`start`].
+#[derive(Debug, Default)]
+pub struct StartOptions {
+    /// Bytes of Lua memory the driver may hold, if less than the server's limit. Zero keeps the
+    /// server's limit.
+    pub memory_limit: Option<u64>,
+    /// Sandbox profile for the driver: `minimal`, `standard` (the default) or `trusted`.
+    pub sandbox: Option<String>,
+    /// Whether the driver is launched again after it finishes or fails.
+    pub restart: RestartPolicy,
+    /// Restarts allowed before the driver is left terminated; unlimited if `None`.
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart, doubling after each one; the server decides if `None`.
+    pub restart_delay: Option<Duration>,
+    /// Arguments passed to the script.
+    pub args: Arguments,
+    /// Name for the driver, unique among the server's running drivers; derived from the script's
+    /// file name if `None`.
+    pub name: Option<String>,
+    /// Labels for picking the driver out with a selector such as `app=web`.
+    pub labels: HashMap<String, String>,
+    /// Identifier for the driver; a random UUID if `None`.
+    pub driver_id: Option<String>,
+    /// Kill any driver with the same identifier or running under the same name instead of failing.
+    pub replace: bool,
+    /// Least severe level the script's `log` functions emit; the server decides if `None`.
+    pub log_level: Option<log::Level>,
+}
+
+/// Which log records [`listen`] asks the server for; records must pass every filter that is set.
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// Least severe level to receive.
+    pub level: Option<log::Level>,
+    /// Targets to receive records from, including targets nested below them.
+    pub targets: Vec<String>,
+    /// Identifiers, names or label selectors of the drivers to receive records about.
+    pub drivers: Vec<String>,
+    /// Regular expression messages must match.
+    pub grep: Option<String>,
+    /// Glob, with `*` and `?` wildcards, whole messages must match.
+    pub glob: Option<String>,
+    /// How many recent records kept by the server to receive before new ones.
+    pub replay: Option<u32>,
+    /// Only replay records logged at or after this time.
+    pub since: Option<SystemTime>,
+    /// What the server should do if the client falls too far behind.
+    pub overflow: LogOverflow,
+}
+
+/// Reads a point in time as an RFC 3339 timestamp or as a duration ago, such as `10m`.
+pub fn parse_since(since: &str) -> Result<SystemTime, InvalidArgument> {
+    humantime::parse_rfc3339_weak(since)
+        .or_else(|_| {
+            humantime::parse_duration(since).map(|ago| {
+                SystemTime::now()
+                    .checked_sub(ago)
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+            })
+        })
+        .map_err(|_| InvalidArgument(format!("expected a timestamp or a duration: {since}")))
+}
+
+/// Named arguments for a driver, which the script receives as a table.
+#[derive(Clone, Debug, Default)]
+pub struct Arguments(serde_json::Map<String, serde_json::Value>);
+
+impl Arguments {
+    /// Takes the arguments from a JSON object.
+    pub fn from_json(json: &str) -> Result<Self, InvalidArgument> {
+        match serde_json::from_str(json) {
+            Ok(serde_json::Value::Object(object)) => Ok(Self(object)),
+            Ok(_) => Err(InvalidArgument(format!("expected a JSON object: {json}"))),
+            Err(error) => Err(InvalidArgument(error.to_string())),
+        }
+    }
+
+    /// Sets an argument from `key=value`, which is always a string, or `key:=json`, which keeps
+    /// the type of a JSON number, boolean, array or object.
+    pub fn set(&mut self, assignment: &str) -> Result<(), InvalidArgument> {
+        let (key, value) = assignment.split_once('=').ok_or_else(|| {
+            InvalidArgument(format!("expected key=value or key:=json: {assignment}"))
+        })?;
+        let (key, value) = match key.strip_suffix(':') {
+            Some(key) => (
+                key,
+                serde_json::from_str(value)
+                    .map_err(|error| InvalidArgument(format!("{assignment}: {error}")))?,
+            ),
+            None => (key, serde_json::Value::String(value.to_string())),
+        };
+
+        self.0.insert(key.to_string(), value);
+
+        Ok(())
+    }
+
+    fn to_proto(&self) -> prost_types::Struct {
+        prost_types::Struct {
+            fields: self
+                .0
+                .iter()
+                .map(|(key, value)| (key.clone(), json_to_value(value)))
+                .collect(),
+        }
+    }
+}
+
 async fn read_source(path: &Path) -> Result<Vec<u8>, UberClientError> {
     let mut file = tokio::fs::File::open(path).await?;
     let mut source = Vec::new();
@@ -36,35 +222,138 @@ async fn read_source(path: &Path) -> Result<Vec<u8>, UberClientError> {
     Ok(source)
 }
 
-async fn write_bytecode(path: &Path, bytecode: &Vec<u8>) -> Result<(), UberClientError> {
-    let path = path.with_extension("luac");
-    let mut file = tokio::fs::File::create(path).await?;
-
-    file.write_all(bytecode)
-        .await
-        .map_err(UberClientError::from)
-}
-
+/// Reads a script and checks that it compiles before it is sent to the server, which only accepts
+/// source text.
 async fn load_script(path: &Path) -> Result<Vec<u8>, UberClientError> {
     let source = read_source(path).await?;
     let lua = mlua::Lua::new();
-    let function = lua
-        .load(&source)
+    lua.load(&source)
         .set_name(path.as_os_str().to_str().unwrap())?
+        .set_mode(mlua::ChunkMode::Text)
         .into_function()?;
-    let bytecode = function.dump(false);
-    log::debug!("{bytecode:X?}");
-    write_bytecode(path, &bytecode).await?;
 
-    Ok(bytecode)
+    Ok(source)
+}
+
+async fn connect_channel(endpoint: &Endpoint) -> Result<Channel, UberClientError> {
+    let channel = match endpoint {
+        Endpoint::Unix(socket) => {
+            let socket = socket.clone();
+
+            tonic::transport::Endpoint::from_static(UDS_URI)
+                .connect_with_connector(service_fn(move |_| UnixStream::connect(socket.clone())))
+                .await?
+        }
+        Endpoint::Tcp { url, tls } => {
+            let mut endpoint = tonic::transport::Endpoint::from_shared(url.clone())?;
+
+            if url.starts_with("https:") {
+                endpoint = endpoint.tls_config(tls.config()?)?;
+            }
+
+            endpoint.connect().await?
+        }
+    };
+
+    Ok(channel)
+}
+
+async fn connect(endpoint: &Endpoint) -> Result<DriverClient<Channel>, UberClientError> {
+    Ok(DriverClient::new(connect_channel(endpoint).await?))
+}
+
+fn format_timestamp(timestamp: Option<prost_types::Timestamp>) -> Option<String> {
+    let time = SystemTime::try_from(timestamp?).ok()?;
+
+    Some(humantime::format_rfc3339_seconds(time).to_string())
+}
+
+/// Renders a log record on one line: time, sequence number, level, target, driver, message,
+/// fields sorted by key and finally the source location.
+fn format_log_event(event: LogEvent) -> String {
+    let timestamp = event
+        .timestamp
+        .clone()
+        .and_then(|timestamp| SystemTime::try_from(timestamp).ok())
+        .map(|time| humantime::format_rfc3339_millis(time).to_string())
+        .unwrap_or_default();
+    let level = log::Level::from(event.level());
+    let mut line = format!(
+        "{timestamp}  {:>6}  {level:<5}  {}",
+        event.seq, event.target
+    );
+
+    if !event.driver_id.is_empty() {
+        line.push_str(&format!("  {}", event.driver_id));
+    }
+
+    line.push_str(&format!("  {}", event.message));
+
+    let mut fields = event.fields.into_iter().collect::<Vec<_>>();
+    fields.sort();
+
+    for (key, value) in fields {
+        line.push_str(&format!("  {key}={value}"));
+    }
+
+    match (event.file.as_str(), event.line) {
+        ("", _) => {}
+        (file, Some(number)) => line.push_str(&format!("  ({file}:{number})")),
+        (file, None) => line.push_str(&format!("  ({file})")),
+    }
+
+    line
+}
+
+fn value_to_json(value: prost_types::Value) -> serde_json::Value {
+    use prost_types::value::Kind;
+
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => serde_json::Value::Null,
+        Some(Kind::BoolValue(value)) => serde_json::Value::Bool(value),
+        Some(Kind::NumberValue(value)) => serde_json::Number::from_f64(value)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Some(Kind::StringValue(value)) => serde_json::Value::String(value),
+        Some(Kind::ListValue(list)) => {
+            serde_json::Value::Array(list.values.into_iter().map(value_to_json).collect())
+        }
+        Some(Kind::StructValue(object)) => serde_json::Value::Object(
+            object
+                .fields
+                .into_iter()
+                .map(|(key, value)| (key, value_to_json(value)))
+                .collect(),
+        ),
+    }
 }
 
-pub async fn listen() -> Result<(), UberClientError> {
+fn json_to_value(value: &serde_json::Value) -> prost_types::Value {
+    use prost_types::value::Kind;
+
+    let kind = match value {
+        serde_json::Value::Null => Kind::NullValue(0),
+        serde_json::Value::Bool(value) => Kind::BoolValue(*value),
+        serde_json::Value::Number(value) => Kind::NumberValue(value.as_f64().unwrap_or_default()),
+        serde_json::Value::String(value) => Kind::StringValue(value.clone()),
+        serde_json::Value::Array(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.iter().map(json_to_value).collect(),
+        }),
+        serde_json::Value::Object(object) => Kind::StructValue(prost_types::Struct {
+            fields: object
+                .iter()
+                .map(|(key, value)| (key.clone(), json_to_value(value)))
+                .collect(),
+        }),
+    };
+
+    prost_types::Value { kind: Some(kind) }
+}
+
+pub async fn listen(endpoint: &Endpoint, options: LogOptions) -> Result<(), UberClientError> {
     env_logger::init();
 
-    let channel = tonic::transport::Endpoint::from_static(UDS_URI)
-        .connect_with_connector(service_fn(|_| UnixStream::connect(UDS_PATH)))
-        .await?;
+    let channel = connect_channel(endpoint).await?;
 
     {
         let channel = channel.clone();
@@ -86,46 +375,301 @@ pub async fn listen() -> Result<(), UberClientError> {
     }
 
     let mut client = DriverClient::new(channel);
-    let mut stream = client.log_events(()).await?.into_inner();
-
-    while let Some(message) = stream.message().await? {
-        let level = log::Level::from(message.level());
-        let target = message.target.as_str();
+    let request = tonic::Request::new(LogEventsRequest {
+        level: options.level.map(|level| LogLevel::from(level) as i32),
+        targets: options.targets,
+        drivers: options.drivers,
+        grep: options.grep.unwrap_or_default(),
+        glob: options.glob.unwrap_or_default(),
+        replay: options.replay,
+        since: options.since.map(prost_types::Timestamp::from),
+        overflow: options.overflow as i32,
+    });
+    let mut stream = client.log_events(request).await?.into_inner();
 
-        log::log!(target: target, level, "{}", message.message);
+    while let Some(event) = stream.message().await? {
+        println!("{}", format_log_event(event));
     }
 
     Ok(())
 }
 
-pub async fn start(path: &Path) -> Result<(), UberClientError> {
+pub async fn start(
+    endpoint: &Endpoint,
+    path: &Path,
+    options: StartOptions,
+) -> Result<(), UberClientError> {
     env_logger::init();
     log::info!("start script {path:?}");
-    let channel = tonic::transport::Endpoint::from_static(UDS_URI)
-        .connect_with_connector(service_fn(|_| UnixStream::connect(UDS_PATH)))
-        .await?;
-    let mut client = DriverClient::new(channel);
-    let driver_id = uuid::Uuid::new_v4().to_string();
+    let mut client = connect(endpoint).await?;
     let payload = load_script(path).await?;
-    let request = tonic::Request::new(StartDriverRequest { driver_id, payload });
+    let StartOptions {
+        memory_limit,
+        sandbox,
+        restart,
+        max_restarts,
+        restart_delay,
+        args,
+        name,
+        labels,
+        driver_id,
+        replace,
+        log_level,
+    } = options;
+    let driver_id = driver_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    // names must be unique, so the default one is made so with the start of the identifier
+    let name = name.unwrap_or_else(|| {
+        let stem = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let prefix = driver_id.chars().take(8).collect::<String>();
+
+        format!("{stem}-{prefix}")
+    });
+    let request = tonic::Request::new(StartDriverRequest {
+        driver_id,
+        payload,
+        name,
+        memory_limit,
+        sandbox: sandbox.unwrap_or_default(),
+        restart: restart as i32,
+        max_restarts,
+        restart_delay: restart_delay.map(prost_types::Duration::from),
+        args: Some(args.to_proto()),
+        labels,
+        replace,
+        log_level: log_level.map(|level| LogLevel::from(level) as i32),
+    });
     log::info!("request: {request:?}");
     let response = client.start_driver(request).await?;
     log::info!("response: {response:?}");
+    let response = response.into_inner();
+
+    if let Some(error) = response.error {
+        return Err(UberClientError::Rejected(error));
+    }
+
+    // the identifier is printed so scripts can pass it to `wait` or `stop`
+    println!("{}", response.driver_id);
 
     Ok(())
 }
 
-pub async fn stop(driver_id: String) -> Result<(), UberClientError> {
+/// Stops the driver with the given identifier or name, or every driver matching a label selector,
+/// and prints the identifiers of the drivers stopped.
+pub async fn stop(
+    endpoint: &Endpoint,
+    driver_id: String,
+    grace_period: Option<Duration>,
+) -> Result<(), UberClientError> {
     env_logger::init();
     log::info!("stop script {driver_id}");
-    let channel = tonic::transport::Endpoint::from_static(UDS_URI)
-        .connect_with_connector(service_fn(|_| UnixStream::connect(UDS_PATH)))
-        .await?;
-    let mut client = DriverClient::new(channel);
-    let request = tonic::Request::new(StopDriverRequest { driver_id });
+    let mut client = connect(endpoint).await?;
+    let grace_period = grace_period.map(prost_types::Duration::from);
+    let request = tonic::Request::new(StopDriverRequest {
+        driver_id,
+        grace_period,
+    });
     log::info!("request: {request:?}");
     let response = client.stop_driver(request).await?;
     log::info!("response: {response:?}");
 
+    for driver_id in response.into_inner().driver_ids {
+        println!("{driver_id}");
+    }
+
     Ok(())
 }
+
+/// Changes the level the `log` functions of running drivers emit from, printing the identifiers
+/// of the drivers it applied to.
+pub async fn set_log_level(
+    endpoint: &Endpoint,
+    driver_id: String,
+    level: log::Level,
+) -> Result<(), UberClientError> {
+    env_logger::init();
+    log::info!("set log level of {driver_id} to {level}");
+    let mut client = connect(endpoint).await?;
+    let request = tonic::Request::new(SetLogLevelRequest {
+        driver_id,
+        level: LogLevel::from(level) as i32,
+    });
+    log::info!("request: {request:?}");
+    let response = client.set_log_level(request).await?;
+    log::info!("response: {response:?}");
+
+    for driver_id in response.into_inner().driver_ids {
+        println!("{driver_id}");
+    }
+
+    Ok(())
+}
+
+/// Lists every driver, or those matching an identifier, name or label selector.
+pub async fn list(
+    endpoint: &Endpoint,
+    selector: Option<String>,
+    json: bool,
+) -> Result<(), UberClientError> {
+    env_logger::init();
+    let mut client = connect(endpoint).await?;
+    let request = tonic::Request::new(ListDriversRequest {
+        selector: selector.unwrap_or_default(),
+    });
+    log::info!("request: {request:?}");
+    let response = client.list_drivers(request).await?;
+    log::info!("response: {response:?}");
+    let drivers = response.into_inner().drivers;
+
+    if json {
+        let drivers = drivers.into_iter().map(driver_to_json).collect();
+
+        println!("{:#}", serde_json::Value::Array(drivers));
+    } else {
+        println!(
+            "{:<36}  {:<20}  {:<10}  {:<8}  {:<20}  {:<20}  LABELS",
+            "ID", "NAME", "STATUS", "RESTARTS", "STARTED", "LAST YIELD"
+        );
+
+        for driver in drivers {
+            let mut labels = driver
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>();
+            labels.sort();
+
+            println!(
+                "{:<36}  {:<20}  {:<10}  {:<8}  {:<20}  {:<20}  {}",
+                driver.driver_id,
+                driver.name,
+                driver.status().to_string(),
+                driver.restart_count,
+                format_timestamp(driver.start_time).unwrap_or_default(),
+                format_timestamp(driver.last_yield_time).unwrap_or_else(|| "-".to_string()),
+                labels.join(","),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn driver_to_json(driver: DriverInfo) -> serde_json::Value {
+    let status: DriverStatus = driver.status();
+    let restart = driver.restart();
+
+    serde_json::json!({
+        "driver_id": driver.driver_id,
+        "name": driver.name,
+        "labels": driver.labels,
+        "status": status.to_string(),
+        "start_time": format_timestamp(driver.start_time),
+        "last_yield_time": format_timestamp(driver.last_yield_time),
+        "error": driver.error,
+        "memory_used": driver.memory_used,
+        "memory_limit": driver.memory_limit,
+        "restart": restart.to_string(),
+        "restart_count": driver.restart_count,
+        "end_time": format_timestamp(driver.end_time),
+        "values": driver.values.into_iter().map(value_to_json).collect::<Vec<_>>(),
+    })
+}
+
+/// Blocks until a driver has terminated for good, then prints its return values or error and
+/// returns how it terminated.
+pub async fn wait(endpoint: &Endpoint, driver_id: String) -> Result<DriverStatus, UberClientError> {
+    env_logger::init();
+    let mut client = connect(endpoint).await?;
+    let request = tonic::Request::new(GetDriverRequest {
+        driver_id,
+        wait: true,
+    });
+    log::info!("request: {request:?}");
+    let response = client.get_driver(request).await?;
+    log::info!("response: {response:?}");
+    let driver = response.into_inner();
+    let status = driver.status();
+
+    match status {
+        DriverStatus::Finished => {
+            let values = driver.values.into_iter().map(value_to_json).collect();
+
+            println!("{}", serde_json::Value::Array(values));
+        }
+        _ => match driver.error {
+            Some(error) => eprintln!("{status}: {error}"),
+            None => eprintln!("{status}"),
+        },
+    }
+
+    Ok(status)
+}
+
+pub async fn events(endpoint: &Endpoint, driver_ids: Vec<String>) -> Result<(), UberClientError> {
+    env_logger::init();
+    let mut client = connect(endpoint).await?;
+    let request = tonic::Request::new(DriverEventsRequest { driver_ids });
+    log::info!("request: {request:?}");
+    let mut stream = client.driver_events(request).await?.into_inner();
+
+    while let Some(event) = stream.message().await? {
+        let timestamp = format_timestamp(event.timestamp).unwrap_or_default();
+        let driver_id = event.driver_id;
+        let description = match event.event {
+            Some(Event::Started(started)) => format!("started {}", started.name),
+            Some(Event::Yielded(yielded)) if !yielded.builtin.is_empty() => {
+                format!("yielded {} {}", yielded.opcode, yielded.builtin)
+            }
+            Some(Event::Yielded(yielded)) => format!("yielded {}", yielded.opcode),
+            Some(Event::Completed(completed)) => {
+                let values = completed.values.into_iter().map(value_to_json).collect();
+
+                format!("completed {}", serde_json::Value::Array(values))
+            }
+            Some(Event::Failed(failed)) => {
+                format!("failed {}\n{}", failed.error, failed.traceback)
+            }
+            Some(Event::Killed(_)) => "killed".to_string(),
+            Some(Event::Restarted(restarted)) => {
+                format!("restarted ({})", restarted.restart_count)
+            }
+            Some(Event::Dropped(dropped)) => format!("{} events dropped", dropped.count),
+            None => continue,
+        };
+
+        println!("{timestamp}  {driver_id}  {description}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn arguments_are_strings_unless_typed() {
+        let mut args = Arguments::default();
+
+        args.set("version=1.10").unwrap();
+        args.set("flag=true").unwrap();
+        args.set("query=a=b").unwrap();
+        args.set("count:=3").unwrap();
+        args.set("ratio:=1.10").unwrap();
+        args.set("tags:=[\"a\", \"b\"]").unwrap();
+
+        assert_eq!(args.0["version"], json!("1.10"));
+        assert_eq!(args.0["flag"], json!("true"));
+        assert_eq!(args.0["query"], json!("a=b"));
+        assert_eq!(args.0["count"], json!(3));
+        assert_eq!(args.0["ratio"], json!(1.1));
+        assert_eq!(args.0["tags"], json!(["a", "b"]));
+        assert!(args.set("bad:=1.10.1").is_err());
+        assert!(args.set("novalue").is_err());
+    }
+}
@@ -23,3 +23,56 @@ impl From<LogLevel> for log::Level {
         }
     }
 }
+
+impl std::fmt::Display for DriverStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            DriverStatus::Running => "running",
+            DriverStatus::Sleeping => "sleeping",
+            DriverStatus::Finished => "finished",
+            DriverStatus::Errored => "errored",
+            DriverStatus::Killed => "killed",
+            DriverStatus::Stopping => "stopping",
+            DriverStatus::Restarting => "restarting",
+        };
+
+        f.write_str(status)
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let policy = match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        };
+
+        f.write_str(policy)
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!("unknown restart policy: {value}")),
+        }
+    }
+}
+
+impl std::str::FromStr for LogOverflow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "drop-oldest" => Ok(LogOverflow::DropOldest),
+            "disconnect" => Ok(LogOverflow::Disconnect),
+            _ => Err(format!("unknown overflow policy: {value}")),
+        }
+    }
+}
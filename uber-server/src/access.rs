@@ -0,0 +1,174 @@
+use crate::{unixstream::UdsConnectInfo, UberServerError};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::{fmt, str::FromStr};
+
+/// What a caller is trying to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Listing drivers and following the log and event streams.
+    Observe,
+    Start,
+    /// Stopping drivers and changing their log level.
+    Stop,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Observe => write!(f, "observe"),
+            Permission::Start => write!(f, "start drivers"),
+            Permission::Stop => write!(f, "stop drivers"),
+        }
+    }
+}
+
+/// A user or group allowed by an [`AccessPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Principal {
+    Uid(u32),
+    Gid(u32),
+}
+
+impl FromStr for Principal {
+    type Err = UberServerError;
+
+    /// Parses `uid:N`, `gid:N`, `user:NAME` or `group:NAME`, resolving names immediately.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| UberServerError::InvalidConfig(format!("{reason}: {value}"));
+        let (kind, name) = value
+            .split_once(':')
+            .ok_or_else(|| invalid("expected uid:, gid:, user: or group:"))?;
+
+        match kind {
+            "uid" => name
+                .parse()
+                .map(Principal::Uid)
+                .map_err(|_| invalid("invalid uid")),
+            "gid" => name
+                .parse()
+                .map(Principal::Gid)
+                .map_err(|_| invalid("invalid gid")),
+            "user" => User::from_name(name)
+                .map_err(std::io::Error::from)?
+                .map(|user| Principal::Uid(user.uid.as_raw()))
+                .ok_or_else(|| invalid("unknown user")),
+            "group" => Group::from_name(name)
+                .map_err(std::io::Error::from)?
+                .map(|group| Principal::Gid(group.gid.as_raw()))
+                .ok_or_else(|| invalid("unknown group")),
+            _ => Err(invalid("expected uid:, gid:, user: or group:")),
+        }
+    }
+}
+
+/// Who may call which RPCs.
+///
+/// Unix socket callers are identified by their peer credentials; only the primary group of the
+/// calling process is known. Root and the user the server runs as are always allowed. Callers on
+/// the TCP listener have no credentials to check, so they are allowed everything if they presented
+/// a client certificate and nothing otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    pub start: Vec<Principal>,
+    pub stop: Vec<Principal>,
+    /// Callers allowed to observe; anyone allowed to start or stop drivers may also observe.
+    pub observe: Vec<Principal>,
+}
+
+impl AccessPolicy {
+    pub(crate) fn allows(&self, caller: &Caller, permission: Permission) -> bool {
+        let (uid, gid) = match caller {
+            Caller::Unix { uid, gid, .. } => (*uid, *gid),
+            Caller::Tcp { certified, .. } => return *certified,
+            Caller::Unknown => return false,
+        };
+
+        if uid == 0 || uid == Uid::effective().as_raw() {
+            return true;
+        }
+
+        let matches = |principals: &[Principal]| {
+            principals.iter().any(|principal| match principal {
+                Principal::Uid(allowed) => *allowed == uid,
+                Principal::Gid(allowed) => *allowed == gid,
+            })
+        };
+
+        match permission {
+            Permission::Start => matches(&self.start),
+            Permission::Stop => matches(&self.stop),
+            Permission::Observe => {
+                matches(&self.observe) || matches(&self.start) || matches(&self.stop)
+            }
+        }
+    }
+}
+
+/// The peer on the other end of a request, as far as the transport can tell.
+#[derive(Debug)]
+pub(crate) enum Caller {
+    Unix {
+        uid: u32,
+        gid: u32,
+        pid: Option<i32>,
+    },
+    Tcp {
+        address: std::net::SocketAddr,
+        certified: bool,
+    },
+    Unknown,
+}
+
+impl Caller {
+    pub fn from_request<T>(request: &tonic::Request<T>) -> Self {
+        if let Some(info) = request.extensions().get::<UdsConnectInfo>() {
+            return match &info.peer_cred {
+                Some(cred) => Caller::Unix {
+                    uid: cred.uid(),
+                    gid: cred.gid(),
+                    pid: cred.pid(),
+                },
+                None => Caller::Unknown,
+            };
+        }
+
+        match request.remote_addr() {
+            Some(address) => Caller::Tcp {
+                address,
+                certified: request.peer_certs().is_some_and(|certs| !certs.is_empty()),
+            },
+            None => Caller::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Caller::Unix { uid, gid, pid } => {
+                write!(f, "uid {uid}")?;
+                if let Ok(Some(user)) = User::from_uid(Uid::from_raw(*uid)) {
+                    write!(f, " ({})", user.name)?;
+                }
+                write!(f, ", gid {gid}")?;
+                if let Ok(Some(group)) = Group::from_gid(Gid::from_raw(*gid)) {
+                    write!(f, " ({})", group.name)?;
+                }
+                if let Some(pid) = pid {
+                    write!(f, ", pid {pid}")?;
+                }
+
+                Ok(())
+            }
+            Caller::Tcp { address, certified } => {
+                write!(f, "{address}")?;
+                if *certified {
+                    write!(f, " with a client certificate")?;
+                }
+
+                Ok(())
+            }
+            Caller::Unknown => write!(f, "unknown caller"),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::BTreeMap, fmt, future::Future, pin::Pin, process::Output, rc::Rc};
+use tokio::process::Command;
+
+/// The future returned by [`AsyncBuiltin::call`]; drivers all run on one thread, so it need not be
+/// `Send`.
+pub type BuiltinFuture<'a, 'lua> =
+    Pin<Box<dyn Future<Output = mlua::Result<mlua::MultiValue<'lua>>> + 'a>>;
+
+/// A host function that drivers call like any other global but which runs asynchronously on the
+/// server, suspending only the calling driver until it completes.
+pub trait AsyncBuiltin {
+    /// The global name the function is exposed under.
+    fn name(&self) -> &str;
+
+    /// Handles one call from the driver `driver_id`. The returned values become the results of the
+    /// call in Lua, and an error is raised in the driver at the call site.
+    fn call<'a, 'lua: 'a>(
+        &'a self,
+        lua: &'lua mlua::Lua,
+        driver_id: &'a str,
+        args: mlua::MultiValue<'lua>,
+    ) -> BuiltinFuture<'a, 'lua>;
+}
+
+/// A set of async builtins, keyed by name.
+///
+/// Registered builtins are visible to drivers running with the `standard` or `trusted` sandbox
+/// profiles.
+#[derive(Clone, Default)]
+pub struct Builtins {
+    builtins: BTreeMap<String, Rc<dyn AsyncBuiltin>>,
+}
+
+impl Builtins {
+    /// Adds a builtin, replacing any previously registered under the same name.
+    pub fn register(&mut self, builtin: impl AsyncBuiltin + 'static) {
+        self.insert(Rc::new(builtin));
+    }
+
+    pub(crate) fn insert(&mut self, builtin: Rc<dyn AsyncBuiltin>) {
+        self.builtins.insert(builtin.name().to_string(), builtin);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Rc<dyn AsyncBuiltin>> {
+        self.builtins.get(name).cloned()
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.builtins.keys().map(String::as_str)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Rc<dyn AsyncBuiltin>> {
+        self.builtins.values()
+    }
+}
+
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// Creates the Lua function that forwards calls of `name` to the executor.
+pub(crate) fn create_function<'lua>(
+    lua: &'lua mlua::Lua,
+    opcode: i32,
+    name: &str,
+) -> mlua::Result<mlua::Function<'lua>> {
+    lua.load(mlua::chunk! {
+        local error, pack, unpack, yield = error, table.pack, table.unpack, coroutine.yield
+
+        return function(...)
+            local results = pack(yield($opcode, $name, ...))
+
+            if not results[1] then
+                error(results[2], 2)
+            end
+
+            return unpack(results, 2, results.n)
+        end
+    })
+    .set_name("=builtin")?
+    .eval()
+}
+
+/// `get_date()`: runs the `date` command, returning `{status, stdout, stderr}` or `nil, message`.
+pub(crate) struct GetDate;
+
+impl AsyncBuiltin for GetDate {
+    fn name(&self) -> &str {
+        "get_date"
+    }
+
+    fn call<'a, 'lua: 'a>(
+        &'a self,
+        lua: &'lua mlua::Lua,
+        _driver_id: &'a str,
+        _args: mlua::MultiValue<'lua>,
+    ) -> BuiltinFuture<'a, 'lua> {
+        Box::pin(async move {
+            let output = match Command::new("date").kill_on_drop(true).output().await {
+                Ok(output) => output,
+                Err(error) => {
+                    let values = (mlua::Value::Nil, error.to_string());
+
+                    return mlua::ToLuaMulti::to_lua_multi(values, lua);
+                }
+            };
+            let Output {
+                status,
+                stdout,
+                stderr,
+            } = output;
+
+            let table = lua.create_table()?;
+            table.set("status", status.to_string())?;
+            table.set("stdout", lua.create_string(&stdout)?)?;
+            table.set("stderr", lua.create_string(&stderr)?)?;
+
+            Ok(mlua::MultiValue::from_vec(vec![mlua::Value::Table(table)]))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Executor};
+    use mlua::{FromLua, ToLuaMulti};
+    use std::time::Duration;
+    use tokio::task::LocalSet;
+    use uber_protos::{DriverStatus, StartDriverRequest};
+
+    /// `double(n)`: returns `n * 2` after a pause, and fails for negative numbers.
+    struct Double;
+
+    impl AsyncBuiltin for Double {
+        fn name(&self) -> &str {
+            "double"
+        }
+
+        fn call<'a, 'lua: 'a>(
+            &'a self,
+            lua: &'lua mlua::Lua,
+            _driver_id: &'a str,
+            args: mlua::MultiValue<'lua>,
+        ) -> BuiltinFuture<'a, 'lua> {
+            Box::pin(async move {
+                let n = f64::from_lua(args.into_iter().next().unwrap_or(mlua::Nil), lua)?;
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                if n < 0.0 {
+                    return Err(mlua::Error::RuntimeError(format!("{n} is negative")));
+                }
+
+                (n * 2.0).to_lua_multi(lua)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_builtins_return_values_and_raise_errors() {
+        LocalSet::new()
+            .run_until(async {
+                let mut builtins = Builtins::default();
+                builtins.register(Double);
+                let config = Config {
+                    builtins,
+                    ..Default::default()
+                };
+                let mut executor = Executor::new(&config).unwrap();
+
+                for (driver_id, source) in [
+                    ("caught", "local ok, error = pcall(double, -1) return double(21), ok, error"),
+                    ("uncaught", "double(-1)"),
+                ] {
+                    let request = StartDriverRequest {
+                        driver_id: driver_id.to_string(),
+                        payload: source.as_bytes().to_vec(),
+                        ..Default::default()
+                    };
+                    executor.create_coroutine(request).unwrap();
+                }
+
+                let mut infos = Vec::new();
+                for driver_id in ["caught", "uncaught"] {
+                    let info = executor.get_driver(driver_id, true).unwrap();
+
+                    infos.push(
+                        tokio::time::timeout(Duration::from_secs(20), info)
+                            .await
+                            .expect("driver did not terminate")
+                            .unwrap(),
+                    );
+                }
+                let (caught, uncaught) = (infos.remove(0), infos.remove(0));
+
+                assert_eq!(caught.status(), DriverStatus::Finished, "{:?}", caught.error);
+                let kinds = caught
+                    .values
+                    .into_iter()
+                    .map(|value| value.kind.unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(kinds[0], prost_types::value::Kind::NumberValue(42.0));
+                assert_eq!(kinds[1], prost_types::value::Kind::BoolValue(false));
+                assert!(
+                    matches!(&kinds[2], prost_types::value::Kind::StringValue(error) if error.contains("-1 is negative")),
+                    "{:?}",
+                    kinds[2]
+                );
+
+                assert_eq!(uncaught.status(), DriverStatus::Errored);
+                let error = uncaught.error.unwrap();
+                assert!(error.contains("-1 is negative"), "{error}");
+                assert!(!error.contains("builtin.rs"), "{error}");
+            })
+            .await;
+    }
+}
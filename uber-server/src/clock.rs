@@ -0,0 +1,225 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone, Timelike,
+    Utc,
+};
+use std::{fmt::Write, time::Instant};
+
+/// Where a wall-clock time is rendered or interpreted.
+enum Zone {
+    Local,
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    /// Accepts `nil` or `"UTC"`, `"local"` for the server's zone, or an IANA name.
+    fn parse(name: Option<String>) -> mlua::Result<Self> {
+        match name.as_deref() {
+            None => Ok(Zone::Named(chrono_tz::UTC)),
+            Some("local") => Ok(Zone::Local),
+            Some(name) => name
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| mlua::Error::RuntimeError(format!("unknown time zone: {name}"))),
+        }
+    }
+
+    fn to_zoned(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => time.with_timezone(&Local).fixed_offset(),
+            Zone::Named(tz) => time.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    fn localize(&self, time: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(time)
+                .earliest()
+                .map(|time| time.fixed_offset()),
+            Zone::Named(tz) => tz
+                .from_local_datetime(time)
+                .earliest()
+                .map(|time| time.fixed_offset()),
+        }
+    }
+}
+
+fn from_epoch(seconds: Option<f64>) -> mlua::Result<DateTime<Utc>> {
+    let seconds = match seconds {
+        Some(seconds) => seconds,
+        None => return Ok(Utc::now()),
+    };
+    // the casts below would turn NaN into the epoch and infinities into the extremes of i64
+    if !seconds.is_finite() {
+        return Err(mlua::Error::RuntimeError(format!(
+            "time out of range: {seconds}"
+        )));
+    }
+    let nanos = (seconds.rem_euclid(1.0) * 1e9) as u32;
+
+    DateTime::from_timestamp(seconds.floor() as i64, nanos)
+        .ok_or_else(|| mlua::Error::RuntimeError(format!("time out of range: {seconds}")))
+}
+
+fn to_epoch<Tz: TimeZone>(time: &DateTime<Tz>) -> f64 {
+    time.timestamp() as f64 + f64::from(time.timestamp_subsec_nanos()) / 1e9
+}
+
+fn parse(text: &str, format: Option<&str>, zone: &Zone) -> Result<DateTime<FixedOffset>, String> {
+    let format = match format {
+        Some(format) => format,
+        None => return DateTime::parse_from_rfc3339(text).map_err(|error| error.to_string()),
+    };
+
+    // prefer an offset in the text itself, then fall back to reading it as a local time
+    if let Ok(time) = DateTime::parse_from_str(text, format) {
+        return Ok(time);
+    }
+
+    let time = match NaiveDateTime::parse_from_str(text, format) {
+        Ok(time) => time,
+        Err(error) => NaiveDate::parse_from_str(text, format)
+            .map(|date| date.and_time(Default::default()))
+            .map_err(|_| error.to_string())?,
+    };
+
+    zone.localize(&time)
+        .ok_or_else(|| format!("{text} does not exist in the requested time zone"))
+}
+
+/// Renders a time with a strftime format, rejecting the format rather than panicking when it has
+/// an unknown specifier or asks for something the time cannot provide.
+fn strftime(time: &DateTime<FixedOffset>, format: &str) -> mlua::Result<String> {
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+
+    if items.contains(&Item::Error) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "invalid time format: {format}"
+        )));
+    }
+
+    let mut text = String::new();
+    write!(text, "{}", time.format_with_items(items.iter()))
+        .map_err(|_| mlua::Error::RuntimeError(format!("cannot format time with {format}")))?;
+
+    Ok(text)
+}
+
+/// Builds the `time` library exposed to drivers.
+///
+/// Wall-clock times are passed around as seconds since the Unix epoch so they can be compared and
+/// subtracted directly in Lua.
+pub fn create_library(lua: &mlua::Lua) -> mlua::Result<mlua::Table<'_>> {
+    let epoch = Instant::now();
+    let library = lua.create_table()?;
+
+    library.set(
+        "now",
+        lua.create_function(|_, ()| Ok(to_epoch(&Utc::now())))?,
+    )?;
+    library.set(
+        "now_ms",
+        lua.create_function(|_, ()| Ok(Utc::now().timestamp_millis()))?,
+    )?;
+    library.set(
+        "monotonic",
+        lua.create_function(move |_, ()| Ok(epoch.elapsed().as_secs_f64()))?,
+    )?;
+    library.set(
+        "format",
+        lua.create_function(
+            |_, (seconds, format, zone): (Option<f64>, Option<String>, Option<String>)| {
+                let time = Zone::parse(zone)?.to_zoned(from_epoch(seconds)?);
+
+                match format {
+                    Some(format) => strftime(&time, &format),
+                    None => Ok(time.to_rfc3339()),
+                }
+            },
+        )?,
+    )?;
+    library.set(
+        "parse",
+        lua.create_function(
+            |_, (text, format, zone): (String, Option<String>, Option<String>)| {
+                let zone = Zone::parse(zone)?;
+
+                Ok(match parse(&text, format.as_deref(), &zone) {
+                    Ok(time) => (Some(to_epoch(&time)), None),
+                    Err(error) => (None, Some(error)),
+                })
+            },
+        )?,
+    )?;
+    library.set(
+        "date",
+        lua.create_function(|lua, (seconds, zone): (Option<f64>, Option<String>)| {
+            let time = Zone::parse(zone)?.to_zoned(from_epoch(seconds)?);
+            let table = lua.create_table()?;
+
+            table.set("year", time.year())?;
+            table.set("month", time.month())?;
+            table.set("day", time.day())?;
+            table.set("hour", time.hour())?;
+            table.set("min", time.minute())?;
+            table.set("sec", time.second())?;
+            table.set("nanos", time.nanosecond())?;
+            // numbered like `os.date("*t")`, with Sunday as 1
+            table.set("wday", time.weekday().number_from_sunday())?;
+            table.set("yday", time.ordinal())?;
+            table.set("utc_offset", time.offset().fix().local_minus_utc())?;
+
+            Ok(table)
+        })?,
+    )?;
+
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_formats_are_lua_errors() {
+        let lua = mlua::Lua::new();
+        lua.globals()
+            .set("time", create_library(&lua).unwrap())
+            .unwrap();
+
+        for format in ["%Q", "%", "%Y-%"] {
+            let error = lua
+                .load(&format!("return time.format(0, {format:?})"))
+                .eval::<String>()
+                .unwrap_err();
+
+            assert!(error.to_string().contains("invalid time format"), "{error}");
+        }
+
+        let text = lua
+            .load(r#"return time.format(0, "%Y-%m-%d %H:%M")"#)
+            .eval::<String>()
+            .unwrap();
+        assert_eq!(text, "1970-01-01 00:00");
+    }
+
+    #[test]
+    fn non_finite_times_are_lua_errors() {
+        let lua = mlua::Lua::new();
+        lua.globals()
+            .set("time", create_library(&lua).unwrap())
+            .unwrap();
+
+        for seconds in ["0/0", "math.huge", "-math.huge"] {
+            for call in ["time.format", "time.date"] {
+                let error = lua
+                    .load(&format!("return {call}({seconds})"))
+                    .eval::<mlua::Value>()
+                    .unwrap_err();
+
+                assert!(error.to_string().contains("time out of range"), "{error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    access::AccessPolicy, builtin::Builtins, exec::ExecPolicy, sandbox::Profile, UberServerError,
+};
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+/// Where clients find the server unless told otherwise.
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/uber-driver.sock";
+
+/// Environment variable overriding the socket path, read by both the server and the client.
+pub const SOCKET_PATH_ENV: &str = "UBER_DRIVER_SOCKET";
+
+/// Settings for a server, shared by the gRPC service and the executor.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Maximum number of Lua instructions a driver may execute between yields, or zero to let
+    /// drivers run unchecked.
+    pub instruction_budget: u32,
+    /// Bytes of Lua memory a driver may hold unless its start request says otherwise, or zero for
+    /// no limit.
+    pub memory_limit: usize,
+    /// Programs drivers may run with the `exec` builtin.
+    pub exec: ExecPolicy,
+    /// Sandbox profiles start requests may ask for.
+    pub sandboxes: Vec<Profile>,
+    /// Additional async builtins offered to drivers.
+    pub builtins: Builtins,
+    /// The Unix socket the server listens on.
+    pub socket: SocketConfig,
+    /// An additional TCP listener for remote clients.
+    pub tcp: Option<TcpConfig>,
+    /// Who may call which RPCs, or `None` to allow anyone who can connect.
+    pub access: Option<AccessPolicy>,
+    /// Directory where drivers are recorded so they can be relaunched when the server restarts,
+    /// or `None` to forget them on exit.
+    pub state_dir: Option<PathBuf>,
+    /// How long a terminated driver, with its return values, is kept after it terminated for good.
+    pub retention: Duration,
+    /// How many recent log records are kept for `LogEvents` to replay.
+    pub log_history: usize,
+    /// How many records a `LogEvents` or `DriverEvents` client may fall behind before its overflow
+    /// policy applies; driver events always drop the oldest.
+    pub log_queue: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            instruction_budget: 1_000_000,
+            memory_limit: 64 * 1024 * 1024,
+            exec: ExecPolicy::default(),
+            sandboxes: vec![Profile::Minimal, Profile::Standard],
+            builtins: Builtins::default(),
+            socket: SocketConfig::default(),
+            tcp: None,
+            access: None,
+            state_dir: None,
+            retention: Duration::from_secs(60 * 60),
+            log_history: crate::logger::DEFAULT_HISTORY_CAPACITY,
+            log_queue: crate::logqueue::DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+}
+
+impl Config {
+    /// Reads a TOML config file; settings it leaves out keep their defaults.
+    ///
+    /// ```toml
+    /// instruction_budget = 1000000
+    /// memory_limit = 67108864
+    /// allow_exec = ["date"]
+    /// allow_exec_env = ["TZ"]
+    /// allow_sandbox = ["minimal", "standard"]
+    /// state_dir = "/var/lib/uber-driver"
+    /// retention = 3600
+    /// log_history = 1000
+    /// log_queue = 1024
+    ///
+    /// [socket]
+    /// path = "/run/uber-driver.sock"
+    /// mode = "660"
+    /// owner = "root"
+    /// group = "uber"
+    ///
+    /// [tcp]
+    /// address = "0.0.0.0:7878"
+    /// cert = "/etc/uber-driver/server.pem"
+    /// key = "/etc/uber-driver/server.key"
+    /// client_ca = "/etc/uber-driver/clients.pem"
+    ///
+    /// [access]
+    /// start = ["user:deploy"]
+    /// stop = ["user:deploy", "group:operators"]
+    /// observe = ["gid:100"]
+    /// ```
+    pub fn from_file(path: &Path) -> Result<Self, UberServerError> {
+        let text = std::fs::read_to_string(path)?;
+        let file: ConfigFile = toml::from_str(&text).map_err(|error| {
+            UberServerError::InvalidConfig(format!("{}: {error}", path.display()))
+        })?;
+        let mut config = Self::default();
+
+        if let Some(instruction_budget) = file.instruction_budget {
+            config.instruction_budget = instruction_budget;
+        }
+
+        if let Some(memory_limit) = file.memory_limit {
+            config.memory_limit = memory_limit;
+        }
+
+        if let Some(allow_exec) = file.allow_exec {
+            config.exec.allowlist = allow_exec;
+        }
+
+        if let Some(allow_exec_env) = file.allow_exec_env {
+            config.exec.env = allow_exec_env;
+        }
+
+        if let Some(allow_sandbox) = file.allow_sandbox {
+            config.sandboxes = allow_sandbox
+                .iter()
+                .map(|profile| profile.parse())
+                .collect::<Result<_, _>>()?;
+        }
+
+        config.state_dir = file.state_dir;
+
+        if let Some(retention) = file.retention {
+            config.retention = parse_seconds(retention)?;
+        }
+
+        if let Some(log_history) = file.log_history {
+            config.log_history = log_history;
+        }
+
+        if let Some(log_queue) = file.log_queue {
+            config.log_queue = log_queue;
+        }
+
+        if let Some(socket) = file.socket {
+            if let Some(path) = socket.path {
+                config.socket.path = path;
+            }
+
+            if let Some(mode) = socket.mode {
+                config.socket.mode = Some(parse_mode(&mode)?);
+            }
+
+            config.socket.owner = socket.owner.or(config.socket.owner);
+            config.socket.group = socket.group.or(config.socket.group);
+        }
+
+        if let Some(tcp) = file.tcp {
+            config.tcp = Some(TcpConfig {
+                address: tcp.address,
+                cert: tcp.cert,
+                key: tcp.key,
+                client_ca: tcp.client_ca,
+                allow_anonymous: tcp.allow_anonymous.unwrap_or(false),
+            });
+        }
+
+        if let Some(access) = file.access {
+            let parse = |principals: Vec<String>| {
+                principals
+                    .iter()
+                    .map(|principal| principal.parse())
+                    .collect::<Result<Vec<_>, _>>()
+            };
+
+            config.access = Some(AccessPolicy {
+                start: parse(access.start)?,
+                stop: parse(access.stop)?,
+                observe: parse(access.observe)?,
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// Applies settings taken from the environment, which override the config file.
+    pub fn apply_env(&mut self) {
+        if let Some(path) = std::env::var_os(SOCKET_PATH_ENV) {
+            self.socket.path = PathBuf::from(path);
+        }
+    }
+}
+
+/// Where the server's Unix socket is created and who may connect to it.
+#[derive(Clone, Debug)]
+pub struct SocketConfig {
+    pub path: PathBuf,
+    /// Permission bits for the socket file, or `None` to leave them to the umask.
+    pub mode: Option<u32>,
+    /// User that owns the socket file, as a name or a numeric id.
+    pub owner: Option<String>,
+    /// Group that owns the socket file, as a name or a numeric id.
+    pub group: Option<String>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(DEFAULT_SOCKET_PATH),
+            mode: None,
+            owner: None,
+            group: None,
+        }
+    }
+}
+
+/// A TCP listener; connections to it are always secured with TLS.
+#[derive(Clone, Debug)]
+pub struct TcpConfig {
+    pub address: SocketAddr,
+    /// PEM file holding the server's certificate chain.
+    pub cert: PathBuf,
+    /// PEM file holding the private key for `cert`.
+    pub key: PathBuf,
+    /// PEM file of CA certificates. When set, clients must present a certificate issued by one of
+    /// them.
+    pub client_ca: Option<PathBuf>,
+    /// Accept clients without a certificate when `client_ca` is unset. Without an access policy
+    /// such clients may do anything, so the listener refuses to start without one or the other.
+    pub allow_anonymous: bool,
+}
+
+impl TcpConfig {
+    pub fn tls_config(&self) -> Result<ServerTlsConfig, UberServerError> {
+        if self.client_ca.is_none() && !self.allow_anonymous {
+            return Err(UberServerError::InvalidConfig(format!(
+                "the TCP listener on {} needs client_ca unless allow_anonymous is set",
+                self.address
+            )));
+        }
+
+        let cert = std::fs::read(&self.cert)?;
+        let key = std::fs::read(&self.key)?;
+        let mut tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        if let Some(client_ca) = &self.client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(std::fs::read(client_ca)?));
+        }
+
+        Ok(tls)
+    }
+}
+
+/// Parses permission bits written in octal, such as `660` or `0o660`.
+pub fn parse_mode(mode: &str) -> Result<u32, UberServerError> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| UberServerError::InvalidConfig(format!("invalid socket mode: {mode}")))
+}
+
+/// Converts a number of seconds given in a config file or on the command line.
+pub fn parse_seconds(secs: f64) -> Result<Duration, UberServerError> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| UberServerError::InvalidConfig(format!("invalid number of seconds: {secs}")))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    instruction_budget: Option<u32>,
+    memory_limit: Option<usize>,
+    allow_exec: Option<Vec<String>>,
+    allow_exec_env: Option<Vec<String>>,
+    allow_sandbox: Option<Vec<String>>,
+    state_dir: Option<PathBuf>,
+    retention: Option<f64>,
+    log_history: Option<usize>,
+    log_queue: Option<usize>,
+    socket: Option<SocketFile>,
+    tcp: Option<TcpFile>,
+    access: Option<AccessFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SocketFile {
+    path: Option<PathBuf>,
+    mode: Option<String>,
+    owner: Option<String>,
+    group: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TcpFile {
+    address: SocketAddr,
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+    allow_anonymous: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccessFile {
+    #[serde(default)]
+    start: Vec<String>,
+    #[serde(default)]
+    stop: Vec<String>,
+    #[serde(default)]
+    observe: Vec<String>,
+}
@@ -0,0 +1,262 @@
+use crate::sandbox::Profile;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{oneshot, Notify},
+    task::JoinHandle,
+};
+use uber_protos::{DriverInfo, DriverStatus, LogLevel, RestartPolicy, StartDriverRequest};
+
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+pub type DriverRef = Rc<RefCell<Driver>>;
+
+#[derive(Debug)]
+pub struct Driver {
+    pub driver_id: String,
+    pub name: String,
+    pub labels: HashMap<String, String>,
+    pub source: Vec<u8>,
+    pub args: prost_types::Struct,
+    pub profile: Profile,
+    pub status: DriverStatus,
+    pub start_time: SystemTime,
+    pub last_yield_time: Option<SystemTime>,
+    pub error: Option<String>,
+    /// Values returned by the script when it finished.
+    pub values: Vec<prost_types::Value>,
+    /// When the driver terminated for good, after any restarts.
+    pub end_time: Option<SystemTime>,
+    /// Callers waiting for the driver to terminate for good.
+    pub waiters: Vec<oneshot::Sender<DriverInfo>>,
+    pub memory_limit: usize,
+    pub memory_used: usize,
+    pub restart: RestartPolicy,
+    pub max_restarts: Option<u32>,
+    pub restart_delay: Duration,
+    pub restart_count: u32,
+    pub task: Option<JoinHandle<()>>,
+    pub stopping: bool,
+    pub stop_signal: Rc<Notify>,
+    pub on_stop: Option<mlua::RegistryKey>,
+    /// The table of arguments, until it is handed to the script when it is first resumed.
+    pub args_table: Option<mlua::RegistryKey>,
+    /// Least severe level the script's `log` functions emit.
+    pub log_level: log::LevelFilter,
+}
+
+impl Driver {
+    pub fn new(request: &StartDriverRequest, profile: Profile, memory_limit: usize) -> Self {
+        let driver_id = request.driver_id.clone();
+        let name = if request.name.is_empty() {
+            driver_id.clone()
+        } else {
+            request.name.clone()
+        };
+        let restart_delay = request
+            .restart_delay
+            .clone()
+            .and_then(|delay| Duration::try_from(delay).ok())
+            .unwrap_or(DEFAULT_RESTART_DELAY);
+
+        Self {
+            driver_id,
+            name,
+            labels: request.labels.clone(),
+            source: request.payload.clone(),
+            args: request.args.clone().unwrap_or_default(),
+            profile,
+            status: DriverStatus::Running,
+            start_time: SystemTime::now(),
+            last_yield_time: None,
+            error: None,
+            values: Vec::new(),
+            end_time: None,
+            waiters: Vec::new(),
+            memory_limit,
+            memory_used: 0,
+            restart: request.restart(),
+            max_restarts: request.max_restarts,
+            restart_delay,
+            restart_count: 0,
+            task: None,
+            stopping: false,
+            stop_signal: Rc::new(Notify::new()),
+            on_stop: None,
+            args_table: None,
+            log_level: request
+                .log_level
+                .and_then(LogLevel::from_i32)
+                .map_or(log::LevelFilter::Info, |level| {
+                    log::Level::from(level).to_level_filter()
+                }),
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        matches!(
+            self.status,
+            DriverStatus::Finished | DriverStatus::Errored | DriverStatus::Killed
+        )
+    }
+
+    pub fn yielded(&mut self, status: DriverStatus) {
+        self.last_yield_time = Some(SystemTime::now());
+
+        if !self.is_terminated() {
+            self.status = status;
+        }
+    }
+
+    pub fn resumed(&mut self) {
+        if !self.is_terminated() {
+            self.status = DriverStatus::Running;
+        }
+    }
+
+    /// Returns `false` if the driver had already terminated for another reason.
+    pub fn terminate(&mut self, status: DriverStatus) -> bool {
+        if self.is_terminated() {
+            return false;
+        }
+
+        self.status = status;
+
+        true
+    }
+
+    /// How long to wait before launching the terminated driver again, or `None` if its restart
+    /// policy says it is done.
+    pub fn next_restart(&self) -> Option<Duration> {
+        let wanted = match self.status {
+            DriverStatus::Finished => self.restart == RestartPolicy::Always,
+            DriverStatus::Errored => self.restart != RestartPolicy::Never,
+            _ => false,
+        };
+
+        let exhausted = self
+            .max_restarts
+            .is_some_and(|max_restarts| self.restart_count >= max_restarts);
+
+        if !wanted || exhausted || self.stopping {
+            return None;
+        }
+
+        let backoff = 2u32.saturating_pow(self.restart_count.min(31));
+
+        Some(
+            self.restart_delay
+                .saturating_mul(backoff)
+                .min(MAX_RESTART_DELAY),
+        )
+    }
+
+    /// Marks the driver as waiting to be launched again, forgetting the outcome of the previous
+    /// run so that a driver killed in the meantime does not report it.
+    pub fn restarting(&mut self) {
+        self.status = DriverStatus::Restarting;
+        self.error = None;
+        self.values.clear();
+        self.memory_used = 0;
+    }
+
+    /// Marks the driver as running again once it has been launched.
+    pub fn restarted(&mut self) {
+        self.status = DriverStatus::Running;
+        self.restart_count += 1;
+    }
+
+    /// Records that the driver will not run again and answers everyone waiting for it.
+    pub fn finish(&mut self) {
+        if self.end_time.is_none() {
+            self.end_time = Some(SystemTime::now());
+        }
+
+        let info = self.info();
+
+        for waiter in self.waiters.drain(..) {
+            let _ = waiter.send(info.clone());
+        }
+    }
+
+    /// Whether the driver terminated for good more than `retention` ago.
+    pub fn is_expired(&self, retention: Duration) -> bool {
+        self.end_time
+            .and_then(|end_time| end_time.elapsed().ok())
+            .is_some_and(|elapsed| elapsed >= retention)
+    }
+
+    /// Bytes the driver may still allocate, or `None` if it is not limited.
+    pub fn memory_available(&self) -> Option<usize> {
+        match self.memory_limit {
+            0 => None,
+            limit => Some(limit.saturating_sub(self.memory_used)),
+        }
+    }
+
+    /// Attributes the change in the size of the Lua heap across a resume to this driver.
+    ///
+    /// Drivers share one heap, so this is approximate: garbage left by one driver may be
+    /// collected while another is running.
+    pub fn account_memory(&mut self, before: usize, after: usize) {
+        self.memory_used = if after >= before {
+            self.memory_used.saturating_add(after - before)
+        } else {
+            self.memory_used.saturating_sub(before - after)
+        };
+    }
+
+    pub fn fail(&mut self, error: String) -> bool {
+        let terminated = self.terminate(DriverStatus::Errored);
+
+        if terminated {
+            self.error = Some(error);
+        }
+
+        terminated
+    }
+
+    /// Asks the script to wind down, interrupting a pending `sleep`.
+    pub fn request_stop(&mut self) {
+        self.stopping = true;
+        self.stop_signal.notify_one();
+    }
+
+    /// Hands out the `on_stop` callback once a stop has been requested.
+    pub fn take_stop_handler(&mut self) -> Option<mlua::RegistryKey> {
+        if self.stopping && !self.is_terminated() {
+            self.on_stop.take()
+        } else {
+            None
+        }
+    }
+
+    pub fn info(&self) -> DriverInfo {
+        let status = if self.stopping && !self.is_terminated() {
+            DriverStatus::Stopping
+        } else {
+            self.status
+        };
+
+        DriverInfo {
+            driver_id: self.driver_id.clone(),
+            name: self.name.clone(),
+            labels: self.labels.clone(),
+            status: status as i32,
+            start_time: Some(self.start_time.into()),
+            last_yield_time: self.last_yield_time.map(Into::into),
+            error: self.error.clone(),
+            memory_used: self.memory_used as u64,
+            memory_limit: self.memory_limit as u64,
+            restart: self.restart as i32,
+            restart_count: self.restart_count,
+            end_time: self.end_time.map(Into::into),
+            values: self.values.clone(),
+        }
+    }
+}
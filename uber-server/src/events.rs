@@ -0,0 +1,52 @@
+use crate::{driver::Driver, selector::Selector, service::EventSender};
+use std::{cell::RefCell, rc::Rc, time::SystemTime};
+use uber_protos::{driver_event::Event, DriverEvent};
+
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Rc<RefCell<Vec<Subscriber>>>,
+}
+
+struct Subscriber {
+    selectors: Vec<Selector>,
+    sender: EventSender,
+}
+
+impl Subscriber {
+    fn wants(&self, driver: &Driver) -> bool {
+        self.selectors.is_empty()
+            || self
+                .selectors
+                .iter()
+                .any(|selector| selector.matches(driver))
+    }
+}
+
+impl EventBus {
+    /// Forwards the events of drivers matching any of `selectors`, or of every driver if there
+    /// are none.
+    pub fn subscribe(&self, selectors: Vec<Selector>, sender: EventSender) {
+        log::info!("forwarding driver events to {sender:?}");
+
+        self.subscribers
+            .borrow_mut()
+            .push(Subscriber { selectors, sender });
+    }
+
+    pub fn emit(&self, driver: &Driver, event: Event) {
+        let event = DriverEvent {
+            driver_id: driver.driver_id.clone(),
+            timestamp: Some(SystemTime::now().into()),
+            event: Some(event),
+        };
+
+        // subscribers whose stream has been dropped are pruned as a side effect
+        self.subscribers.borrow_mut().retain(|subscriber| {
+            if subscriber.wants(driver) {
+                subscriber.sender.send(event.clone())
+            } else {
+                !subscriber.sender.is_closed()
+            }
+        });
+    }
+}
@@ -0,0 +1,381 @@
+use crate::builtin::{AsyncBuiltin, BuiltinFuture};
+use mlua::{FromLua, ToLua};
+use std::{
+    collections::HashMap,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+/// Bytes of stdout, and separately of stderr, kept from a program; the rest is discarded.
+const MAX_OUTPUT: u64 = 1024 * 1024;
+
+/// Decides which programs drivers may run with the `exec` builtin.
+#[derive(Clone, Debug, Default)]
+pub struct ExecPolicy {
+    /// Programs that may be run, matched exactly against the `cmd` argument. Bare names are
+    /// resolved through the server's `PATH` before the program is spawned. An empty list disables
+    /// `exec`.
+    pub allowlist: Vec<String>,
+    /// Environment variables drivers may set with the `env` option. Programs start with an
+    /// otherwise empty environment.
+    pub env: Vec<String>,
+}
+
+impl ExecPolicy {
+    pub fn allows(&self, program: &str) -> bool {
+        self.allowlist.iter().any(|allowed| allowed == program)
+    }
+
+    pub fn allows_env(&self, name: &str) -> bool {
+        self.env.iter().any(|allowed| allowed == name)
+    }
+}
+
+/// `exec(cmd, args, opts)`: runs a program allowed by the policy, returning
+/// `{status, success, stdout, stderr, truncated}` or `nil, message`.
+pub(crate) struct Exec {
+    pub policy: ExecPolicy,
+}
+
+impl AsyncBuiltin for Exec {
+    fn name(&self) -> &str {
+        "exec"
+    }
+
+    fn call<'a, 'lua: 'a>(
+        &'a self,
+        lua: &'lua mlua::Lua,
+        _driver_id: &'a str,
+        args: mlua::MultiValue<'lua>,
+    ) -> BuiltinFuture<'a, 'lua> {
+        Box::pin(async move {
+            let request = ExecRequest::from_lua_values(args.into_iter(), lua)?;
+
+            // variables such as LD_PRELOAD or BASH_ENV would run code of the driver's choosing
+            if let Some(name) = request
+                .env
+                .keys()
+                .find(|name| !self.policy.allows_env(name))
+            {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "exec may not set {name}"
+                )));
+            }
+
+            Ok(exec(lua, &self.policy, request).await)
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ExecRequest {
+    program: String,
+    args: Vec<String>,
+    timeout: Option<Duration>,
+    cwd: Option<String>,
+    env: HashMap<String, String>,
+    stdin: Option<Vec<u8>>,
+}
+
+impl ExecRequest {
+    /// Reads `cmd, args, opts` as passed to the `exec` builtin.
+    fn from_lua_values<'lua>(
+        mut values: impl Iterator<Item = mlua::Value<'lua>>,
+        lua: &'lua mlua::Lua,
+    ) -> mlua::Result<Self> {
+        let program = match values.next() {
+            Some(value) => String::from_lua(value, lua)?,
+            None => return Err(mlua::Error::RuntimeError("missing command".to_string())),
+        };
+        let args = match values.next() {
+            Some(mlua::Value::Nil) | None => Vec::new(),
+            Some(value) => Vec::<String>::from_lua(value, lua)?,
+        };
+        let opts = match values.next() {
+            Some(mlua::Value::Nil) | None => None,
+            Some(value) => Some(mlua::Table::from_lua(value, lua)?),
+        };
+        let mut request = Self {
+            program,
+            args,
+            timeout: None,
+            cwd: None,
+            env: HashMap::new(),
+            stdin: None,
+        };
+
+        if let Some(opts) = opts {
+            request.timeout = opts
+                .get::<_, Option<f64>>("timeout")?
+                .map(|secs| {
+                    Duration::try_from_secs_f64(secs).map_err(|_| {
+                        mlua::Error::RuntimeError(format!("invalid exec timeout: {secs}"))
+                    })
+                })
+                .transpose()?;
+            request.cwd = opts.get("cwd")?;
+            request.env = opts
+                .get::<_, Option<HashMap<String, String>>>("env")?
+                .unwrap_or_default();
+            request.stdin = opts
+                .get::<_, Option<mlua::String>>("stdin")?
+                .map(|stdin| stdin.as_bytes().to_vec());
+        }
+
+        Ok(request)
+    }
+}
+
+/// Runs a program on behalf of a driver, returning either a result table or `nil, message`.
+async fn exec<'lua>(
+    lua: &'lua mlua::Lua,
+    policy: &ExecPolicy,
+    request: ExecRequest,
+) -> mlua::MultiValue<'lua> {
+    let result = if policy.allows(&request.program) {
+        run(lua, request).await
+    } else {
+        Err(format!(
+            "{} is not allowed by the exec policy",
+            request.program
+        ))
+    };
+
+    let values = match result {
+        Ok(table) => vec![mlua::Value::Table(table)],
+        Err(message) => vec![
+            mlua::Value::Nil,
+            message.to_lua(lua).unwrap_or_else(mlua::Value::Error),
+        ],
+    };
+
+    mlua::MultiValue::from_vec(values)
+}
+
+async fn run<'lua>(
+    lua: &'lua mlua::Lua,
+    request: ExecRequest,
+) -> Result<mlua::Table<'lua>, String> {
+    let ExecRequest {
+        program,
+        args,
+        timeout,
+        cwd,
+        env,
+        stdin,
+    } = request;
+    let mut command = Command::new(resolve(&program)?);
+
+    command
+        .args(&args)
+        .env_clear()
+        .envs(&env)
+        .kill_on_drop(true)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+
+    let mut child = command
+        .spawn()
+        .map_err(|error| format!("{program}: {error}"))?;
+    let input = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let run = async move {
+        let write = async move {
+            if let (Some(mut input), Some(stdin)) = (input, stdin) {
+                // the program may exit without reading its input, which is not an error
+                let _ = input.write_all(&stdin).await;
+            }
+        };
+        let (_, stdout, stderr, status) = tokio::join!(
+            write,
+            read_capped(stdout),
+            read_capped(stderr),
+            child.wait()
+        );
+
+        Ok::<_, std::io::Error>((status?, stdout?, stderr?))
+    };
+    let (status, (stdout, stdout_truncated), (stderr, stderr_truncated)) = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_| format!("{program}: timed out after {timeout:?}"))?,
+        None => run.await,
+    }
+    .map_err(|error| format!("{program}: {error}"))?;
+
+    let table = || -> mlua::Result<mlua::Table<'lua>> {
+        let table = lua.create_table()?;
+        table.set("status", status.code())?;
+        table.set("success", status.success())?;
+        table.set("stdout", lua.create_string(&stdout)?)?;
+        table.set("stderr", lua.create_string(&stderr)?)?;
+        table.set("truncated", stdout_truncated || stderr_truncated)?;
+
+        Ok(table)
+    };
+
+    table().map_err(|error| error.to_string())
+}
+
+/// Reads up to [`MAX_OUTPUT`] bytes of a program's output, then discards the rest so the program
+/// does not block on a full pipe. Returns whether anything was discarded.
+async fn read_capped(reader: Option<impl AsyncRead + Unpin>) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut output = Vec::new();
+    let Some(mut reader) = reader else {
+        return Ok((output, false));
+    };
+
+    (&mut reader)
+        .take(MAX_OUTPUT)
+        .read_to_end(&mut output)
+        .await?;
+    let discarded = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+
+    Ok((output, discarded > 0))
+}
+
+/// Finds the program the allowlist entry names before the driver's `env` and `cwd` apply, so
+/// neither can change which program runs.
+fn resolve(program: &str) -> Result<PathBuf, String> {
+    if program.contains('/') {
+        return std::env::current_dir()
+            .map(|cwd| cwd.join(program))
+            .map_err(|error| format!("{program}: {error}"));
+    }
+
+    std::env::var_os("PATH")
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+        .ok_or_else(|| format!("{program}: not found in PATH"))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(lua: &mlua::Lua, opts: &str) -> mlua::Result<ExecRequest> {
+        let opts = lua.load(opts).eval::<mlua::Value>()?;
+        let values = vec![
+            mlua::Value::String(lua.create_string("true")?),
+            mlua::Value::Nil,
+            opts,
+        ];
+
+        ExecRequest::from_lua_values(values.into_iter(), lua)
+    }
+
+    #[test]
+    fn invalid_timeouts_are_lua_errors() {
+        let lua = mlua::Lua::new();
+
+        for timeout in ["-1", "0/0", "math.huge"] {
+            let error = request(&lua, &format!("{{ timeout = {timeout} }}")).unwrap_err();
+
+            assert!(
+                error.to_string().contains("invalid exec timeout"),
+                "{error}"
+            );
+        }
+
+        let request = request(&lua, "{ timeout = 1.5 }").unwrap();
+        assert_eq!(request.timeout, Some(Duration::from_millis(1500)));
+    }
+
+    /// Runs `exec` with the arguments `args` evaluates to, returning its result table.
+    async fn call<'lua>(
+        lua: &'lua mlua::Lua,
+        policy: &[&str],
+        env: &[&str],
+        args: &str,
+    ) -> mlua::Result<mlua::Table<'lua>> {
+        let exec = Exec {
+            policy: ExecPolicy {
+                allowlist: policy.iter().map(|program| program.to_string()).collect(),
+                env: env.iter().map(|name| name.to_string()).collect(),
+            },
+        };
+        let args = lua.load(args).eval::<mlua::MultiValue>()?;
+        let values = exec.call(lua, "test", args).await?;
+
+        mlua::Table::from_lua(values.into_iter().next().unwrap(), lua)
+    }
+
+    #[tokio::test]
+    async fn env_is_limited_to_the_allowlist() {
+        let lua = mlua::Lua::new();
+
+        for name in ["PATH", "LD_PRELOAD", "BASH_ENV", "PYTHONPATH", "GCONV_PATH"] {
+            let args = format!("return 'env', nil, {{ env = {{ LANG = 'C', {name} = '/tmp' }} }}");
+            let error = call(&lua, &["env"], &["LANG"], &args).await.unwrap_err();
+
+            assert!(error.to_string().contains(name), "{error}");
+        }
+
+        // nothing of the server's environment is passed on
+        let result = call(
+            &lua,
+            &["env"],
+            &["LANG"],
+            "return 'env', nil, { env = { LANG = 'C' } }",
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.get::<_, String>("stdout").unwrap(), "LANG=C\n");
+    }
+
+    #[tokio::test]
+    async fn output_is_capped() {
+        let lua = mlua::Lua::new();
+        let result = call(
+            &lua,
+            &["head"],
+            &[],
+            "return 'head', { '-c', '3000000', '/dev/zero' }",
+        )
+        .await
+        .unwrap();
+
+        assert!(result.get::<_, bool>("success").unwrap());
+        assert!(result.get::<_, bool>("truncated").unwrap());
+        assert_eq!(
+            result
+                .get::<_, mlua::String>("stdout")
+                .unwrap()
+                .as_bytes()
+                .len() as u64,
+            MAX_OUTPUT
+        );
+    }
+
+    #[test]
+    fn bare_names_resolve_to_absolute_paths() {
+        let path = resolve("sh").unwrap();
+
+        assert!(path.is_absolute(), "{path:?}");
+        assert!(resolve("no-such-program-uber-driver").is_err());
+    }
+}
@@ -1,99 +1,728 @@
-use crate::UberServerError;
-use mlua::{FromLua, ToLua, ToLuaMulti};
-use std::{process::Output, rc::Rc, time::Duration};
-use tokio::process::Command;
-use uber_protos::DriverResponse;
+use crate::{
+    builtin::{self, AsyncBuiltin, Builtins, GetDate},
+    clock,
+    driver::{Driver, DriverRef},
+    events::EventBus,
+    exec::Exec,
+    logger::{self, driver_log},
+    sandbox::{self, Profile},
+    selector::{self, Selector},
+    service::EventSender,
+    state::StateDir,
+    value, Config, UberServerError,
+};
+use mlua::{FromLua, FromLuaMulti, ToLuaMulti};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use uber_protos::{
+    driver_event::Event, DriverCompleted, DriverEventsRequest, DriverFailed, DriverInfo,
+    DriverKilled, DriverRestarted, DriverStarted, DriverStatus, DriverYielded, StartDriverRequest,
+};
 
 const REGISTRY_COROUTINES: &str = "REGISTRY_COROUTINES";
-const REGISTRY_SANDBOX: &str = "REGISTRY_SANDBOX";
+
+/// How often the instruction hook fires while a driver is running.
+const HOOK_INSTRUCTION_INTERVAL: u32 = 1000;
+
+const REQUEST_NOOP: i32 = 0;
+const REQUEST_PRINT: i32 = 1;
+const REQUEST_SLEEP: i32 = 2;
+const REQUEST_CALL: i32 = 3;
 
 pub struct Executor {
+    context: Context,
+    drivers: HashMap<String, DriverRef>,
+    memory_limit: usize,
+    sandboxes: Vec<Profile>,
+    retention: Duration,
+}
+
+/// State shared between the executor and the tasks resuming each coroutine.
+#[derive(Clone)]
+struct Context {
     lua: Rc<mlua::Lua>,
+    events: EventBus,
+    instructions: Rc<Cell<u32>>,
+    builtins: Rc<RefCell<Builtins>>,
+    state: Option<Rc<StateDir>>,
 }
 
 impl Executor {
-    pub fn new() -> Result<Self, UberServerError> {
-        let lua = Rc::new(unsafe { mlua::Lua::unsafe_new() });
+    pub fn new(config: &Config) -> Result<Self, UberServerError> {
+        let lua = Rc::new(mlua::Lua::new_with(
+            mlua::StdLib::ALL_SAFE,
+            mlua::LuaOptions::new(),
+        )?);
+        let instruction_budget = config.instruction_budget;
+        let instructions = Rc::new(Cell::new(0u32));
+
+        if instruction_budget > 0 {
+            let instructions = instructions.clone();
+            let interval = HOOK_INSTRUCTION_INTERVAL.min(instruction_budget);
+            let triggers = mlua::HookTriggers {
+                every_nth_instruction: Some(interval),
+                ..Default::default()
+            };
+
+            // threads inherit the hook of the state that created them, so this must be installed
+            // before any coroutine exists
+            lua.set_hook(triggers, move |_, _| {
+                let count = instructions.get().saturating_add(interval);
+                instructions.set(count);
+
+                if count > instruction_budget {
+                    Err(preemption_error(instruction_budget))
+                } else {
+                    Ok(())
+                }
+            })?;
+        }
+
+        // the preemption error is an ordinary Lua error, so the functions that catch errors raise
+        // it again once the budget is spent, whatever they caught; it then unwinds to run_thread
+        let check_preempted = {
+            let instructions = instructions.clone();
+
+            lua.create_function(move |_, ()| {
+                if instruction_budget > 0 && instructions.get() > instruction_budget {
+                    Err(preemption_error(instruction_budget))
+                } else {
+                    Ok(())
+                }
+            })?
+        };
+
+        lua.load(mlua::chunk! {
+            local pcall, xpcall, resume = pcall, xpcall, coroutine.resume
+
+            local function rethrow(ok, ...)
+                if not ok then
+                    $check_preempted()
+                end
+
+                return ok, ...
+            end
+
+            function _G.pcall(...)
+                return rethrow(pcall(...))
+            end
+
+            function _G.xpcall(...)
+                return rethrow(xpcall(...))
+            end
+
+            function coroutine.resume(...)
+                return rethrow(resume(...))
+            end
+        })
+        .exec()?;
 
         let table = lua.create_table()?;
         lua.set_named_registry_value(REGISTRY_COROUTINES, table)?;
 
-        let table = lua
-            .load(mlua::chunk! {
-                local REQUEST_NOOP = 0
-                local REQUEST_PRINT = 1
-                local REQUEST_SLEEP = 2
-                local REQUEST_GETDATE = 3
+        // reports where the function calling it was called from, i.e. the script's location
+        let caller = lua.create_function(|lua, ()| Ok(script_location(lua, 2)))?;
+        // raises the error in the driver rather than when the executor reads the request
+        let check_duration = lua.create_function(|_, secs: Option<f64>| {
+            sleep_duration(secs.unwrap_or_default()).map(|_| secs)
+        })?;
 
-                function noop()
-                    coroutine.yield(REQUEST_NOOP)
-                end
+        lua.load(mlua::chunk! {
+            function noop()
+                coroutine.yield($REQUEST_NOOP)
+            end
 
-                function print(...)
-                    local msg = table.concat({...}, "\t")
+            function print(...)
+                local parts = table.pack(...)
 
-                    coroutine.yield(REQUEST_PRINT, msg)
+                for i = 1, parts.n do
+                    parts[i] = tostring(parts[i])
                 end
 
-                function sleep(duration)
-                    coroutine.yield(REQUEST_SLEEP, duration)
-                end
+                local msg = table.concat(parts, "\t", 1, parts.n)
 
-                function get_date()
-                    return coroutine.yield(REQUEST_GETDATE)
-                end
+                coroutine.yield($REQUEST_PRINT, msg, $caller())
+            end
 
-                return { __index = _G }
-            })
-            .eval::<mlua::Table>()?;
-        lua.set_named_registry_value(REGISTRY_SANDBOX, table)?;
+            function sleep(duration)
+                coroutine.yield($REQUEST_SLEEP, $check_duration(duration))
+            end
+        })
+        .exec()?;
+        lua.globals().set("time", clock::create_library(&lua)?)?;
+        sandbox::protect_shared_state(&lua)?;
+
+        let mut executor = Self {
+            context: Context {
+                lua,
+                events: EventBus::default(),
+                instructions,
+                builtins: Rc::default(),
+                state: match &config.state_dir {
+                    Some(path) => Some(Rc::new(StateDir::open(path)?)),
+                    None => None,
+                },
+            },
+            drivers: HashMap::new(),
+            memory_limit: config.memory_limit,
+            sandboxes: config.sandboxes.clone(),
+            retention: config.retention,
+        };
+
+        executor.register_builtin(GetDate)?;
+        executor.register_builtin(Exec {
+            policy: config.exec.clone(),
+        })?;
+
+        for builtin in config.builtins.iter() {
+            executor.insert_builtin(builtin.clone())?;
+        }
 
-        Ok(Self { lua })
+        Ok(executor)
     }
 
-    pub fn create_coroutine(
+    /// Makes an async builtin available to drivers started from now on, replacing any builtin or
+    /// global function of the same name.
+    pub fn register_builtin(
         &mut self,
-        driver_id: String,
-        bytecode: Vec<u8>,
+        builtin: impl AsyncBuiltin + 'static,
     ) -> Result<(), UberServerError> {
-        store_thread(self.lua.clone(), driver_id.as_str(), &bytecode)?;
+        self.insert_builtin(Rc::new(builtin))
+    }
+
+    fn insert_builtin(&mut self, builtin: Rc<dyn AsyncBuiltin>) -> Result<(), UberServerError> {
+        let lua = &self.context.lua;
+        let function = builtin::create_function(lua, REQUEST_CALL, builtin.name())?;
+
+        lua.globals().set(builtin.name(), function)?;
+        self.context.builtins.borrow_mut().insert(builtin);
+
+        Ok(())
+    }
+
+    /// Launches the drivers recorded in the state directory by a previous run of the server.
+    pub fn restore(&mut self) -> Result<(), UberServerError> {
+        let requests = match &self.context.state {
+            Some(state) => state.load()?,
+            None => return Ok(()),
+        };
 
-        tokio::task::spawn_local(spawn_thread(self.lua.clone(), driver_id));
+        for request in requests {
+            let driver_id = request.driver_id.clone();
+
+            driver_log!(info, &driver_id, "RESTORED");
+
+            if let Err(error) = self.create_coroutine(request) {
+                driver_log!(error, &driver_id, "{error}");
+            }
+        }
 
         Ok(())
     }
 
-    pub fn kill_coroutine(&mut self, driver_id: String) -> DriverResponse {
-        let lua = self.lua.clone();
-        let result = load_thread(&self.lua, driver_id.as_str()).and_then(|thread| {
-            let function = lua.create_function(|_, _: ()| Ok(()))?;
+    pub fn create_coroutine(&mut self, request: StartDriverRequest) -> Result<(), UberServerError> {
+        self.prune();
+
+        let driver_id = &request.driver_id;
+
+        selector::validate_name(driver_id)?;
+        if !request.name.is_empty() {
+            selector::validate_name(&request.name)?;
+        }
+        for (key, value) in &request.labels {
+            selector::validate_label(key, value)?;
+        }
+
+        let profile = request.sandbox.parse::<Profile>()?;
+        if !self.sandboxes.contains(&profile) {
+            return Err(UberServerError::PermissionDenied(format!(
+                "sandbox profile {:?} is not allowed on this server",
+                request.sandbox
+            )));
+        }
+        // a request may tighten the server's limit but neither lift nor raise it
+        let memory_limit = match request.memory_limit.filter(|limit| *limit > 0) {
+            Some(limit) if self.memory_limit > 0 => (limit as usize).min(self.memory_limit),
+            Some(limit) => limit as usize,
+            None => self.memory_limit,
+        };
+        let driver = Driver::new(&request, profile, memory_limit);
+        let name = driver.name.clone();
+
+        // the driver with the same identifier, even one kept after it terminated, and the running
+        // driver with the same name stand in the way unless the request replaces them
+        let mut replaced = Vec::new();
+        if let Some(existing) = self.drivers.get(driver_id) {
+            replaced.push(existing.clone());
+        }
+        let namesake = self.drivers.values().find(|other| {
+            let other = other.borrow();
 
-            thread.reset(function).map_err(UberServerError::LuaError)
+            other.name == name && other.end_time.is_none() && other.driver_id != *driver_id
         });
-        let error = match result {
-            Ok(()) => None,
-            Err(error) => Some(error.to_string()),
+        if let Some(namesake) = namesake {
+            replaced.push(namesake.clone());
+        }
+
+        if !request.replace {
+            if self.drivers.contains_key(driver_id) {
+                return Err(UberServerError::AlreadyExists(driver_id.clone()));
+            }
+
+            if !replaced.is_empty() {
+                return Err(UberServerError::AlreadyExists(format!(
+                    "a driver named {name} is already running"
+                )));
+            }
+        }
+
+        let driver = Rc::new(RefCell::new(driver));
+
+        // the new script must compile before anything it replaces is stopped
+        let thread = create_thread(&self.context, &driver)?;
+
+        for old in replaced {
+            let old_id = old.borrow().driver_id.clone();
+
+            driver_log!(info, &old_id, "REPLACED by {driver_id}");
+            kill_thread(&self.context, &old)?;
+        }
+
+        register_thread(&self.context.lua, driver_id, thread)?;
+
+        if let Some(state) = &self.context.state {
+            if let Err(error) = state.save(&request) {
+                remove_thread(&self.context.lua, driver_id)?;
+
+                return Err(error.into());
+            }
+        }
+
+        self.drivers.insert(driver_id.clone(), driver.clone());
+        self.context
+            .events
+            .emit(&driver.borrow(), Event::Started(DriverStarted { name }));
+
+        let task = tokio::task::spawn_local(spawn_thread(self.context.clone(), driver.clone()));
+        driver.borrow_mut().task = Some(task);
+
+        Ok(())
+    }
+
+    /// Describes the drivers matching `selector`, or every driver, oldest first.
+    pub fn list_drivers(
+        &mut self,
+        selector: Option<&str>,
+    ) -> Result<Vec<DriverInfo>, UberServerError> {
+        self.prune();
+
+        let drivers = match selector {
+            Some(selector) => self.select(&selector.parse()?),
+            None => self.sorted(),
         };
 
-        DriverResponse { driver_id, error }
+        Ok(drivers
+            .iter()
+            .map(|driver| driver.borrow().info())
+            .collect())
+    }
+
+    fn sorted(&self) -> Vec<DriverRef> {
+        let mut drivers = self.drivers.values().cloned().collect::<Vec<_>>();
+
+        drivers.sort_by_key(|driver| driver.borrow().start_time);
+        drivers
+    }
+
+    fn select(&self, selector: &Selector) -> Vec<DriverRef> {
+        let mut drivers = self.sorted();
+
+        drivers.retain(|driver| selector.matches(&driver.borrow()));
+        drivers
+    }
+
+    /// Looks a driver up by identifier, or else by name, preferring the most recently started of
+    /// the drivers that have had that name.
+    fn find(&self, driver_id: &str) -> Result<DriverRef, UberServerError> {
+        if let Some(driver) = self.drivers.get(driver_id) {
+            return Ok(driver.clone());
+        }
+
+        self.sorted()
+            .into_iter()
+            .rev()
+            .find(|driver| driver.borrow().name == driver_id)
+            .ok_or_else(|| UberServerError::DriverNotFound(driver_id.to_string()))
+    }
+
+    /// Describes a driver, either straight away or once it has terminated for good.
+    pub fn get_driver(
+        &mut self,
+        driver_id: &str,
+        wait: bool,
+    ) -> Result<oneshot::Receiver<DriverInfo>, UberServerError> {
+        self.prune();
+
+        let driver = self.find(driver_id)?;
+        let mut driver = driver.borrow_mut();
+        let (info_tx, info_rx) = oneshot::channel();
+
+        if wait && driver.end_time.is_none() {
+            driver.waiters.push(info_tx);
+        } else {
+            let _ = info_tx.send(driver.info());
+        }
+
+        Ok(info_rx)
+    }
+
+    /// Forgets drivers that terminated longer ago than the retention period.
+    fn prune(&mut self) {
+        let retention = self.retention;
+
+        self.drivers
+            .retain(|_, driver| !driver.borrow().is_expired(retention));
+    }
+
+    /// Resolves identifiers, names and label selectors to the identifiers of the drivers they
+    /// currently pick.
+    pub fn resolve_drivers(&self, selectors: &[String]) -> Result<Vec<String>, UberServerError> {
+        let mut driver_ids = Vec::new();
+
+        for selector in selectors {
+            for driver in self.select(&selector.parse()?) {
+                driver_ids.push(driver.borrow().driver_id.clone());
+            }
+        }
+
+        Ok(driver_ids)
+    }
+
+    pub fn subscribe_events(&self, request: DriverEventsRequest, sender: EventSender) {
+        let selectors = request
+            .driver_ids
+            .iter()
+            .map(|selector| selector.parse())
+            .collect::<Result<Vec<Selector>, _>>();
+
+        match selectors {
+            Ok(selectors) => self.context.events.subscribe(selectors, sender),
+            Err(error) => sender.fail(error.into()),
+        }
+    }
+
+    /// Stops the drivers matching `selector`, returning their identifiers. A selector naming a
+    /// single driver must match one; a label selector stops every driver that has not already
+    /// terminated for good, which may be none.
+    pub fn stop_coroutine(
+        &mut self,
+        selector: &str,
+        grace_period: Option<Duration>,
+    ) -> Result<Vec<String>, UberServerError> {
+        self.targets(selector)?
+            .into_iter()
+            .map(|driver| {
+                let driver_id = driver.borrow().driver_id.clone();
+
+                self.stop_driver(driver, grace_period).map(|()| driver_id)
+            })
+            .collect()
+    }
+
+    /// Sets the level the `log` functions of the drivers matching `selector` emit from, returning
+    /// their identifiers. Drivers are picked as by [`Executor::stop_coroutine`].
+    pub fn set_log_level(
+        &mut self,
+        selector: &str,
+        level: log::LevelFilter,
+    ) -> Result<Vec<String>, UberServerError> {
+        let drivers = self.targets(selector)?;
+
+        Ok(drivers
+            .into_iter()
+            .map(|driver| {
+                let driver_id = driver.borrow().driver_id.clone();
+
+                driver_log!(info, &driver_id, "LOG LEVEL {level}");
+                driver.borrow_mut().log_level = level;
+
+                driver_id
+            })
+            .collect())
+    }
+
+    /// The drivers a control request applies to: the one a selector names, or every driver
+    /// matching a label selector that has not terminated for good.
+    fn targets(&mut self, selector: &str) -> Result<Vec<DriverRef>, UberServerError> {
+        self.prune();
+
+        Ok(match selector.parse()? {
+            Selector::Driver(driver_id) => vec![self.find(&driver_id)?],
+            selector => {
+                let mut drivers = self.select(&selector);
+
+                drivers.retain(|driver| driver.borrow().end_time.is_none());
+                drivers
+            }
+        })
+    }
+
+    /// Stops a driver, either immediately or after giving the script a grace period to observe
+    /// `stopping()` and run its `on_stop` callback.
+    fn stop_driver(
+        &self,
+        driver: DriverRef,
+        grace_period: Option<Duration>,
+    ) -> Result<(), UberServerError> {
+        let driver_id = driver.borrow().driver_id.clone();
+
+        match grace_period {
+            Some(grace_period) if !grace_period.is_zero() && !driver.borrow().is_terminated() => {
+                driver_log!(info, &driver_id, "STOPPING");
+                driver.borrow_mut().request_stop();
+
+                let context = self.context.clone();
+
+                tokio::task::spawn_local(async move {
+                    tokio::time::sleep(grace_period).await;
+
+                    if let Err(error) = kill_thread(&context, &driver) {
+                        driver_log!(error, &driver_id, "{error}");
+                    }
+                });
+
+                Ok(())
+            }
+            _ => kill_thread(&self.context, &driver),
+        }
     }
 }
 
-fn store_thread(
-    lua: Rc<mlua::Lua>,
-    driver_id: &str,
-    bytecode: &Vec<u8>,
-) -> Result<(), UberServerError> {
-    let env = lua.create_table()?;
-    let metatable = lua.named_registry_value::<_, mlua::Table>(REGISTRY_SANDBOX)?;
-    env.set_metatable(Some(metatable));
+fn kill_thread(context: &Context, driver: &DriverRef) -> Result<(), UberServerError> {
+    let driver_id = driver.borrow().driver_id.clone();
+
+    // a driver that is done for good may have been replaced by one with the same identifier,
+    // whose coroutine and state record must be left alone
+    if driver.borrow().end_time.is_some() {
+        return Ok(());
+    }
+
+    // dropping the task future cancels any pending sleep and kills child processes
+    if let Some(task) = driver.borrow_mut().task.take() {
+        task.abort();
+    }
+
+    remove_thread(&context.lua, driver_id.as_str())?;
+
+    if driver.borrow_mut().terminate(DriverStatus::Killed) {
+        driver_log!(info, &driver_id, "KILLED");
+        context
+            .events
+            .emit(&driver.borrow(), Event::Killed(DriverKilled {}));
+    }
 
-    let chunk = lua.load(&bytecode);
+    retire(context, driver);
+
+    Ok(())
+}
+
+/// Forgets the state record of a driver that will not run again and reports its outcome.
+fn retire(context: &Context, driver: &DriverRef) {
+    let driver_id = driver.borrow().driver_id.clone();
+
+    driver.borrow_mut().finish();
+
+    if let Some(state) = &context.state {
+        if let Err(error) = state.remove(&driver_id) {
+            driver_log!(error, &driver_id, "{error}");
+        }
+    }
+}
+
+fn store_thread(context: &Context, driver: &DriverRef) -> Result<(), UberServerError> {
+    let thread = create_thread(context, driver)?;
+
+    register_thread(&context.lua, &driver.borrow().driver_id, thread)
+}
+
+/// Compiles a driver's script into a new coroutine without registering it.
+fn create_thread<'lua>(
+    context: &'lua Context,
+    driver: &DriverRef,
+) -> Result<mlua::Thread<'lua>, UberServerError> {
+    let lua = &context.lua;
+    let name = driver.borrow().name.clone();
+    let profile = driver.borrow().profile;
+    let source = driver.borrow().source.clone();
+    let env = sandbox::environment(lua, profile, context.builtins.borrow().names())?;
+
+    let args = value::struct_from_proto(lua, &driver.borrow().args)?;
+    env.set("args", args.clone())?;
+
+    let key = lua.create_registry_value(args)?;
+    if let Some(key) = driver.borrow_mut().args_table.replace(key) {
+        lua.remove_registry_value(key)?;
+    }
+
+    let stopping = {
+        let driver = driver.clone();
+
+        lua.create_function(move |_, ()| Ok(driver.borrow().stopping))?
+    };
+    env.set("stopping", stopping)?;
+
+    let on_stop = {
+        let driver = driver.clone();
+
+        lua.create_function(move |lua, callback: mlua::Function| {
+            let key = lua.create_registry_value(callback)?;
+
+            if let Some(key) = driver.borrow_mut().on_stop.replace(key) {
+                lua.remove_registry_value(key)?;
+            }
+
+            Ok(())
+        })?
+    };
+    env.set("on_stop", on_stop)?;
+    env.set("log", create_log_table(lua, driver)?)?;
+
+    let chunk = lua.load(&source);
     let function = chunk
-        .set_name(driver_id)?
+        .set_name(&name)?
+        .set_mode(mlua::ChunkMode::Text)
         .set_environment(env)?
         .into_function()?;
-    let thread = lua.create_thread(function)?;
+
+    lua.create_thread(function)
+        .map_err(UberServerError::LuaError)
+}
+
+/// The script's `log` table: `log.info("retrying", { attempt = 3 })` and likewise for `trace`,
+/// `debug`, `warn` and `error`, plus `log.level()` and `log.set_level(level)` for the driver's
+/// threshold, which may also be `"off"`.
+fn create_log_table<'lua>(
+    lua: &'lua mlua::Lua,
+    driver: &DriverRef,
+) -> Result<mlua::Table<'lua>, UberServerError> {
+    let table = lua.create_table()?;
+
+    for level in [
+        log::Level::Trace,
+        log::Level::Debug,
+        log::Level::Info,
+        log::Level::Warn,
+        log::Level::Error,
+    ] {
+        let driver = driver.clone();
+        let function = lua.create_function(
+            move |lua, (message, fields): (String, Option<mlua::Table>)| {
+                let driver = driver.borrow();
+
+                if level > driver.log_level {
+                    return Ok(());
+                }
+
+                let fields = match fields {
+                    Some(fields) => log_fields(fields)?,
+                    None => Vec::new(),
+                };
+                let (file, line) = script_location(lua, 1);
+
+                logger::script_log(
+                    level,
+                    &driver.driver_id,
+                    file.as_deref(),
+                    line,
+                    &message,
+                    &fields,
+                );
+
+                Ok(())
+            },
+        )?;
+
+        table.set(level.as_str().to_lowercase(), function)?;
+    }
+
+    let level = {
+        let driver = driver.clone();
+
+        lua.create_function(move |_, ()| Ok(driver.borrow().log_level.as_str().to_lowercase()))?
+    };
+    table.set("level", level)?;
+
+    let set_level = {
+        let driver = driver.clone();
+
+        lua.create_function(move |_, level: String| {
+            let level = level
+                .parse::<log::LevelFilter>()
+                .map_err(|_| mlua::Error::RuntimeError(format!("invalid log level: {level}")))?;
+
+            driver.borrow_mut().log_level = level;
+
+            Ok(())
+        })?
+    };
+    table.set("set_level", set_level)?;
+
+    Ok(table)
+}
+
+/// Turns a table of fields into key/value pairs, which must be strings, numbers or booleans.
+fn log_fields(fields: mlua::Table) -> mlua::Result<Vec<(String, String)>> {
+    fields
+        .pairs::<String, mlua::Value>()
+        .map(|pair| {
+            let (key, value) = pair?;
+            let value = match value {
+                mlua::Value::String(value) => value.to_str()?.to_string(),
+                mlua::Value::Integer(value) => value.to_string(),
+                mlua::Value::Number(value) => value.to_string(),
+                mlua::Value::Boolean(value) => value.to_string(),
+                value => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "log field {key} must be a string, number or boolean, not {}",
+                        value.type_name()
+                    )))
+                }
+            };
+
+            if key == logger::DRIVER_ID_KEY {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "log field {key} is reserved"
+                )));
+            }
+
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// The chunk and line of the Lua function `level` frames up the stack from the running one.
+fn script_location(lua: &mlua::Lua, level: usize) -> (Option<String>, Option<u32>) {
+    let Some(debug) = lua.inspect_stack(level) else {
+        return (None, None);
+    };
+    let source = debug
+        .source()
+        .short_src
+        .map(|src| String::from_utf8_lossy(src).into_owned());
+
+    (source, u32::try_from(debug.curr_line()).ok())
+}
+
+fn register_thread(
+    lua: &mlua::Lua,
+    driver_id: &str,
+    thread: mlua::Thread,
+) -> Result<(), UberServerError> {
     let registry: mlua::Table = lua.named_registry_value(REGISTRY_COROUTINES)?;
 
     registry
@@ -110,89 +739,362 @@ fn load_thread<'lua>(
     mlua::Thread::from_lua(registry.get(driver_id)?, lua).map_err(UberServerError::LuaError)
 }
 
-async fn spawn_thread(lua: Rc<mlua::Lua>, driver_id: String) {
-    let thread = match load_thread(&lua, driver_id.as_str()) {
-        Ok(thread) => thread,
-        Err(error) => {
-            log::error!("{driver_id}: {error}");
-            return;
+fn remove_thread(lua: &mlua::Lua, driver_id: &str) -> Result<(), UberServerError> {
+    let registry = lua.named_registry_value::<_, mlua::Table>(REGISTRY_COROUTINES)?;
+
+    registry
+        .set(driver_id, mlua::Value::Nil)
+        .map_err(UberServerError::LuaError)
+}
+
+/// Runs a driver, launching it again for as long as its restart policy asks.
+async fn spawn_thread(context: Context, driver: DriverRef) {
+    let driver_id = driver.borrow().driver_id.clone();
+
+    loop {
+        run_thread(&context, &driver).await;
+
+        let delay = driver.borrow().next_restart();
+        let delay = match delay {
+            Some(delay) => delay,
+            None => break,
+        };
+
+        if let Some(key) = driver.borrow_mut().on_stop.take() {
+            let _ = context.lua.remove_registry_value(key);
+        }
+        driver.borrow_mut().restarting();
+        driver_log!(info, &driver_id, "RESTARTING in {delay:?}");
+
+        let stop_signal = driver.borrow().stop_signal.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop_signal.notified() => {
+                // there is no script to clean up after, so a graceful stop takes effect at once
+                if driver.borrow_mut().terminate(DriverStatus::Killed) {
+                    driver_log!(info, &driver_id, "KILLED");
+                    context
+                        .events
+                        .emit(&driver.borrow(), Event::Killed(DriverKilled {}));
+                }
+
+                break;
+            }
+        }
+
+        if let Err(error) = store_thread(&context, &driver) {
+            driver_log!(error, &driver_id, "{error}");
+
+            if driver.borrow_mut().fail(error.to_string()) {
+                let error = error.to_string();
+                let traceback = String::new();
+
+                context.events.emit(
+                    &driver.borrow(),
+                    Event::Failed(DriverFailed { error, traceback }),
+                );
+            }
+
+            break;
         }
+
+        let restart_count = {
+            let mut driver = driver.borrow_mut();
+
+            driver.restarted();
+            driver.restart_count
+        };
+        context.events.emit(
+            &driver.borrow(),
+            Event::Restarted(DriverRestarted { restart_count }),
+        );
+    }
+
+    retire(&context, &driver);
+}
+
+/// Resumes a driver's coroutine until it terminates.
+async fn run_thread(context: &Context, driver: &DriverRef) {
+    let Context {
+        lua,
+        events,
+        instructions,
+        builtins,
+        ..
+    } = context;
+    let driver_id = driver.borrow().driver_id.clone();
+    let fail = |(error, traceback): (String, String)| {
+        let message = if traceback.is_empty() {
+            error.clone()
+        } else {
+            format!("{error}\nstack traceback:\n\t{traceback}")
+        };
+
+        driver_log!(error, &driver_id, "{message}");
+
+        if driver.borrow_mut().fail(message) {
+            events.emit(
+                &driver.borrow(),
+                Event::Failed(DriverFailed { error, traceback }),
+            );
+        }
+    };
+    let mut thread = match load_thread(lua, driver_id.as_str()) {
+        Ok(thread) => thread,
+        Err(error) => return fail((error.to_string(), String::new())),
     };
+    let stop_signal = driver.borrow().stop_signal.clone();
 
     let nil = mlua::MultiValue::new();
-    let mut args = Some(nil.clone());
+    let args_table = driver.borrow_mut().args_table.take();
+    let mut args = match args_table {
+        Some(key) => {
+            let table = lua.registry_value::<mlua::Value>(&key);
+            let _ = lua.remove_registry_value(key);
+
+            match table.and_then(|table| table.to_lua_multi(lua)) {
+                Ok(table) => Some(table),
+                Err(error) => return fail(describe(&error)),
+            }
+        }
+        None => Some(nil.clone()),
+    };
 
     while let mlua::ThreadStatus::Resumable = thread.status() {
-        match thread.resume::<_, AsyncRequest>(args.take().unwrap_or(nil.clone())) {
+        let handler = driver.borrow_mut().take_stop_handler();
+        if let Some(key) = handler {
+            // the script asked to be told about stops, so abandon the main chunk for the callback
+            let result = lua
+                .registry_value::<mlua::Function>(&key)
+                .and_then(|callback| lua.create_thread(callback));
+            let _ = lua.remove_registry_value(key);
+
+            match result {
+                Ok(handler) => {
+                    thread = handler;
+                    args = None;
+                }
+                Err(error) => {
+                    fail(describe(&error));
+                    break;
+                }
+            }
+        }
+
+        instructions.set(0);
+
+        let memory_before = lua.used_memory();
+        let memory_available = driver.borrow().memory_available();
+        if let Some(available) = memory_available {
+            let _ = lua.set_memory_limit(memory_before.saturating_add(available));
+        }
+
+        let result = thread.resume::<_, mlua::MultiValue>(args.take().unwrap_or(nil.clone()));
+
+        if memory_available.is_some() {
+            let _ = lua.set_memory_limit(0);
+        }
+        driver
+            .borrow_mut()
+            .account_memory(memory_before, lua.used_memory());
+
+        let values = match result {
+            Ok(values) => values,
+            Err(error)
+                if memory_available.is_some()
+                    && matches!(root_cause(&error), mlua::Error::MemoryError(_)) =>
+            {
+                let memory_limit = driver.borrow().memory_limit;
+
+                fail((
+                    format!("terminated after exceeding its memory limit of {memory_limit} bytes"),
+                    String::new(),
+                ));
+                break;
+            }
+            Err(error) => {
+                fail(describe(&error));
+                break;
+            }
+        };
+
+        if !matches!(thread.status(), mlua::ThreadStatus::Resumable) {
+            driver_log!(info, &driver_id, "TERMINATED");
+
+            if driver.borrow_mut().terminate(DriverStatus::Finished) {
+                let values = value::to_proto_multi(&values);
+
+                driver.borrow_mut().values = values.clone();
+
+                events.emit(
+                    &driver.borrow(),
+                    Event::Completed(DriverCompleted { values }),
+                );
+            }
+
+            break;
+        }
+
+        match AsyncRequest::from_lua_multi(values, lua) {
             Ok(request) => {
-                log::info!("{driver_id}: {request:?}");
+                driver_log!(info, &driver_id, "{request:?}");
+
+                let status = match request {
+                    AsyncRequest::Sleep(_) => DriverStatus::Sleeping,
+                    _ => DriverStatus::Running,
+                };
+                let builtin = match &request {
+                    AsyncRequest::Call { name, .. } => name.clone(),
+                    _ => String::new(),
+                };
+                driver.borrow_mut().yielded(status);
+                events.emit(
+                    &driver.borrow(),
+                    Event::Yielded(DriverYielded {
+                        opcode: request.opcode(),
+                        builtin,
+                    }),
+                );
 
                 match request {
                     AsyncRequest::NoOp => tokio::task::yield_now().await,
-                    AsyncRequest::Print(msg) => {
-                        log::info!("{driver_id}: {msg}");
+                    AsyncRequest::Print {
+                        message,
+                        file,
+                        line,
+                    } => {
+                        logger::script_log(
+                            log::Level::Info,
+                            &driver_id,
+                            file.as_deref(),
+                            line,
+                            &message,
+                            &[],
+                        );
                         tokio::task::yield_now().await;
                     }
-                    AsyncRequest::Sleep(duration) => tokio::time::sleep(duration).await,
-                    AsyncRequest::GetDate => {
-                        let result = Command::new("date")
-                            .output()
-                            .await
-                            .map_err(UberServerError::IoError)
-                            .and_then(
-                                |Output {
-                                     status,
-                                     stdout,
-                                     stderr,
-                                 }| {
-                                    let table = lua.create_table()?;
-                                    table.set("status", status.to_string())?;
-                                    table.set("stdout", String::from_utf8(stdout)?)?;
-                                    table.set("stderr", String::from_utf8(stderr)?)?;
-
-                                    let value = table.to_lua_multi(&lua)?;
-
-                                    Ok(value)
-                                },
-                            )
-                            .map_err(|err| {
-                                let values = vec![
-                                    mlua::Value::Nil,
-                                    err.to_string()
-                                        .to_lua(&lua)
-                                        .unwrap_or_else(|error| mlua::Value::Error(error)),
-                                ];
-
-                                mlua::MultiValue::from_vec(values)
-                            });
-
-                        args.replace(match result {
-                            Ok(value) => value,
-                            Err(value) => value,
-                        });
+                    AsyncRequest::Sleep(duration) => {
+                        tokio::select! {
+                            _ = tokio::time::sleep(duration) => {}
+                            _ = stop_signal.notified() => {}
+                        }
+                        driver.borrow_mut().resumed();
+                    }
+                    AsyncRequest::Call {
+                        name,
+                        args: call_args,
+                    } => {
+                        // keep the builtin alive for the duration of the call even if it is
+                        // replaced in the meantime
+                        let builtin = builtins.borrow().get(&name);
+                        let result = match &builtin {
+                            Some(builtin) => builtin.call(lua, &driver_id, call_args).await,
+                            None => Err(mlua::Error::RuntimeError(format!(
+                                "unknown builtin: {name}"
+                            ))),
+                        };
+                        let values = match result {
+                            Ok(values) => (true, values).to_lua_multi(lua),
+                            Err(error) => (false, error.to_string()).to_lua_multi(lua),
+                        };
+
+                        match values {
+                            Ok(values) => args = Some(values),
+                            Err(error) => {
+                                fail(describe(&error));
+                                break;
+                            }
+                        }
                     }
                 }
             }
-            Err(error) => {
-                if let mlua::ThreadStatus::Resumable = thread.status() {
-                    log::error!("{driver_id}: {error}");
-                } else {
-                    log::info!("{driver_id}: TERMINATED");
-                }
+            Err(error) => driver_log!(error, &driver_id, "{error}"),
+        }
+    }
+
+    if let Err(error) = remove_thread(lua, driver_id.as_str()) {
+        driver_log!(error, &driver_id, "{error}");
+    }
+}
+
+fn sleep_duration(secs: f64) -> mlua::Result<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| mlua::Error::RuntimeError(format!("invalid sleep duration: {secs}")))
+}
+
+fn preemption_error(instruction_budget: u32) -> mlua::Error {
+    mlua::Error::RuntimeError(format!(
+        "preempted after exceeding the budget of {instruction_budget} instructions without yielding"
+    ))
+}
+
+/// Splits an error into its message and the Lua traceback. Errors raised by Rust callbacks, such
+/// as preemption, are reported by their cause rather than as a generic "callback error".
+fn describe(error: &mlua::Error) -> (String, String) {
+    match error {
+        mlua::Error::CallbackError { cause, traceback } => {
+            let (error, inner) = describe(cause);
+            let traceback = if inner.is_empty() {
+                traceback
+                    .strip_prefix("stack traceback:")
+                    .unwrap_or(traceback)
+                    .trim_start()
+                    .to_string()
+            } else {
+                inner
+            };
+
+            (error, traceback)
+        }
+        error => {
+            let error = match error {
+                mlua::Error::RuntimeError(message) => message.clone(),
+                error => error.to_string(),
+            };
+
+            match error.split_once("\nstack traceback:") {
+                Some((error, traceback)) => (error.to_string(), traceback.trim_start().to_string()),
+                None => (error, String::new()),
             }
         }
     }
 }
 
+/// The error a callback error was caused by, however deeply nested.
+fn root_cause(error: &mlua::Error) -> &mlua::Error {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => root_cause(cause),
+        error => error,
+    }
+}
+
 #[derive(Debug)]
-enum AsyncRequest {
+enum AsyncRequest<'lua> {
     NoOp,
-    Print(String),
+    Print {
+        message: String,
+        file: Option<String>,
+        line: Option<u32>,
+    },
     Sleep(Duration),
-    GetDate,
+    Call {
+        name: String,
+        args: mlua::MultiValue<'lua>,
+    },
+}
+
+impl AsyncRequest<'_> {
+    fn opcode(&self) -> i32 {
+        match self {
+            AsyncRequest::NoOp => REQUEST_NOOP,
+            AsyncRequest::Print { .. } => REQUEST_PRINT,
+            AsyncRequest::Sleep(_) => REQUEST_SLEEP,
+            AsyncRequest::Call { .. } => REQUEST_CALL,
+        }
+    }
 }
 
-impl<'lua> mlua::FromLuaMulti<'lua> for AsyncRequest {
+impl<'lua> mlua::FromLuaMulti<'lua> for AsyncRequest<'lua> {
     fn from_lua_multi(values: mlua::MultiValue<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
         let mut values = values.into_iter();
         let opcode = match values.next() {
@@ -201,29 +1103,368 @@ impl<'lua> mlua::FromLuaMulti<'lua> for AsyncRequest {
         };
 
         match opcode {
-            0 => Ok(AsyncRequest::NoOp),
-            1 => {
-                let msg = match values.next() {
+            REQUEST_NOOP => Ok(AsyncRequest::NoOp),
+            REQUEST_PRINT => {
+                let message = match values.next() {
                     Some(value) => String::from_lua(value, lua)?,
                     None => String::default(),
                 };
+                let file = match values.next() {
+                    Some(value) => Option::<String>::from_lua(value, lua)?,
+                    None => None,
+                };
+                let line = match values.next() {
+                    Some(value) => Option::<u32>::from_lua(value, lua)?,
+                    None => None,
+                };
 
-                Ok(AsyncRequest::Print(msg))
+                Ok(AsyncRequest::Print {
+                    message,
+                    file,
+                    line,
+                })
             }
-            2 => {
+            REQUEST_SLEEP => {
                 let secs = match values.next() {
                     Some(value) => f64::from_lua(value, lua)?,
                     None => 0f64,
                 };
 
-                Ok(AsyncRequest::Sleep(Duration::from_secs_f64(secs)))
+                sleep_duration(secs).map(AsyncRequest::Sleep)
             }
-            3 => Ok(AsyncRequest::GetDate),
-            _ => {
-                return Err(mlua::Error::RuntimeError(format!(
-                    "invalid opcode: {opcode}"
-                )))
+            REQUEST_CALL => {
+                let name = match values.next() {
+                    Some(value) => String::from_lua(value, lua)?,
+                    None => return Err(mlua::Error::RuntimeError("missing builtin".to_string())),
+                };
+
+                Ok(AsyncRequest::Call {
+                    name,
+                    args: values.collect(),
+                })
             }
+            _ => Err(mlua::Error::RuntimeError(format!(
+                "invalid opcode: {opcode}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::LocalSet;
+
+    fn request(driver_id: &str, source: &str, sandbox: &str) -> StartDriverRequest {
+        StartDriverRequest {
+            driver_id: driver_id.to_string(),
+            payload: source.as_bytes().to_vec(),
+            sandbox: sandbox.to_string(),
+            ..Default::default()
         }
     }
+
+    fn start(executor: &mut Executor, driver_id: &str, source: &str) {
+        executor
+            .create_coroutine(request(driver_id, source, ""))
+            .unwrap();
+    }
+
+    async fn wait(executor: &mut Executor, driver_id: &str) -> DriverInfo {
+        let info = executor.get_driver(driver_id, true).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(20), info)
+            .await
+            .expect("driver did not terminate")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn preemption_cannot_be_caught() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+
+                start(
+                    &mut executor,
+                    "spin",
+                    "while true do pcall(function() while true do end end) end",
+                );
+                start(
+                    &mut executor,
+                    "ticker",
+                    "local n = 0 for i = 1, 5 do sleep(0.05) n = n + 1 end return n",
+                );
+
+                let spin = wait(&mut executor, "spin").await;
+                let ticker = wait(&mut executor, "ticker").await;
+
+                assert_eq!(spin.status(), DriverStatus::Errored);
+                assert!(
+                    spin.error
+                        .as_deref()
+                        .unwrap()
+                        .starts_with("preempted after"),
+                    "{:?}",
+                    spin.error
+                );
+                assert_eq!(ticker.status(), DriverStatus::Finished);
+                assert_eq!(
+                    ticker.values,
+                    vec![prost_types::Value {
+                        kind: Some(prost_types::value::Kind::NumberValue(5.0)),
+                    }]
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn preemption_cannot_be_caught_by_xpcall() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+
+                start(
+                    &mut executor,
+                    "xpcall",
+                    "while true do xpcall(function() while true do end end, print) end",
+                );
+
+                let info = wait(&mut executor, "xpcall").await;
+
+                assert_eq!(info.status(), DriverStatus::Errored);
+                assert!(
+                    info.error
+                        .as_deref()
+                        .unwrap()
+                        .starts_with("preempted after"),
+                    "{:?}",
+                    info.error
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn preemption_cannot_be_caught_by_coroutines() {
+        LocalSet::new()
+            .run_until(async {
+                let config = Config {
+                    sandboxes: vec![Profile::Trusted],
+                    ..Default::default()
+                };
+                let mut executor = Executor::new(&config).unwrap();
+                let source = "while true do
+                    coroutine.resume(coroutine.create(function() while true do end end))
+                end";
+
+                executor
+                    .create_coroutine(request("resume", source, "trusted"))
+                    .unwrap();
+
+                let info = wait(&mut executor, "resume").await;
+
+                assert_eq!(info.status(), DriverStatus::Errored);
+                assert!(
+                    info.error
+                        .as_deref()
+                        .unwrap()
+                        .starts_with("preempted after"),
+                    "{:?}",
+                    info.error
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn trusted_sandbox_is_refused_by_default() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+                let result = executor.create_coroutine(request("trusted", "return 1", "trusted"));
+
+                assert!(
+                    matches!(result, Err(UberServerError::PermissionDenied(_))),
+                    "{result:?}"
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn sandboxes_hide_host_access() {
+        // each value is true when the capability is out of reach
+        let source = r#"
+            local function unreachable(f)
+                local ok, value = pcall(f)
+                return not ok or value == nil
+            end
+
+            return
+                unreachable(function() return os.execute end),
+                unreachable(function() return os.remove end),
+                unreachable(function() return io.open end),
+                load == nil,
+                loadfile == nil,
+                dofile == nil,
+                require == nil,
+                debug == nil,
+                unreachable(function() return getmetatable("").__index end)
+        "#;
+
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+
+                for sandbox in ["minimal", "standard"] {
+                    executor
+                        .create_coroutine(request(sandbox, source, sandbox))
+                        .unwrap();
+
+                    let info = wait(&mut executor, sandbox).await;
+
+                    assert_eq!(info.status(), DriverStatus::Finished, "{:?}", info.error);
+                    assert_eq!(info.values.len(), 9);
+                    for (index, value) in info.values.iter().enumerate() {
+                        assert_eq!(
+                            value.kind,
+                            Some(prost_types::value::Kind::BoolValue(true)),
+                            "{sandbox} check {index}"
+                        );
+                    }
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn invalid_sleep_durations_fail_the_driver() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+
+                for (driver_id, secs) in [("negative", "-1"), ("nan", "0/0"), ("huge", "math.huge")]
+                {
+                    start(&mut executor, driver_id, &format!("sleep(0) sleep({secs})"));
+
+                    let info = wait(&mut executor, driver_id).await;
+
+                    assert_eq!(info.status(), DriverStatus::Errored);
+                    assert!(
+                        info.error
+                            .as_deref()
+                            .unwrap()
+                            .contains("invalid sleep duration"),
+                        "{:?}",
+                        info.error
+                    );
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn log_level_changes_reach_running_drivers() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+
+                start(
+                    &mut executor,
+                    "logger",
+                    "local before = log.level() sleep(0.2) return before, log.level()",
+                );
+                // let the script read its level before it changes
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let driver_ids = executor
+                    .set_log_level("logger", log::LevelFilter::Debug)
+                    .unwrap();
+
+                let info = wait(&mut executor, "logger").await;
+                let string = |value: &str| prost_types::Value {
+                    kind: Some(prost_types::value::Kind::StringValue(value.to_string())),
+                };
+
+                assert_eq!(driver_ids, vec!["logger".to_string()]);
+                assert_eq!(info.values, vec![string("info"), string("debug")]);
+                assert!(executor
+                    .set_log_level("missing", log::LevelFilter::Debug)
+                    .is_err());
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn memory_limit_overrides_are_capped() {
+        LocalSet::new()
+            .run_until(async {
+                let limit = 1024 * 1024;
+                let config = Config {
+                    memory_limit: limit,
+                    ..Default::default()
+                };
+                let mut executor = Executor::new(&config).unwrap();
+                let source =
+                    "local t = {} for i = 1, 100000 do t[i] = string.rep('x', 100) .. i end";
+
+                for (driver_id, override_limit) in [("raised", 1 << 30), ("lifted", 0)] {
+                    let request = StartDriverRequest {
+                        memory_limit: Some(override_limit),
+                        ..request(driver_id, source, "")
+                    };
+                    executor.create_coroutine(request).unwrap();
+
+                    let info = wait(&mut executor, driver_id).await;
+
+                    assert_eq!(info.status(), DriverStatus::Errored);
+                    assert_eq!(
+                        info.error.as_deref(),
+                        Some(
+                            format!("terminated after exceeding its memory limit of {limit} bytes")
+                                .as_str()
+                        )
+                    );
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn drivers_killed_during_backoff_forget_the_previous_run() {
+        LocalSet::new()
+            .run_until(async {
+                let mut executor = Executor::new(&Config::default()).unwrap();
+                let request = StartDriverRequest {
+                    restart: uber_protos::RestartPolicy::OnFailure as i32,
+                    restart_delay: Some(Duration::from_secs(60).into()),
+                    ..request("backoff", "error('boom')", "")
+                };
+                executor.create_coroutine(request).unwrap();
+
+                let mut status = DriverStatus::Running;
+                for _ in 0..200 {
+                    let info = executor
+                        .get_driver("backoff", false)
+                        .unwrap()
+                        .await
+                        .unwrap();
+
+                    status = info.status();
+                    if status == DriverStatus::Restarting {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                assert_eq!(status, DriverStatus::Restarting);
+
+                executor.stop_coroutine("backoff", None).unwrap();
+
+                let info = wait(&mut executor, "backoff").await;
+                assert_eq!(info.status(), DriverStatus::Killed);
+                assert_eq!(info.error, None);
+            })
+            .await;
+    }
 }
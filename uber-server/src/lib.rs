@@ -1,12 +1,40 @@
-pub use crate::{executor::Executor, listener::Listener, service::Service};
+pub use crate::{
+    access::{AccessPolicy, Principal},
+    builtin::{AsyncBuiltin, BuiltinFuture, Builtins},
+    config::{
+        parse_mode, parse_seconds, Config, SocketConfig, TcpConfig, DEFAULT_SOCKET_PATH,
+        SOCKET_PATH_ENV,
+    },
+    exec::ExecPolicy,
+    executor::Executor,
+    listener::Listener,
+    sandbox::Profile,
+    service::Service,
+};
 use thiserror::Error;
 use tokio::task::LocalSet;
 use uber_protos::driver_server::DriverServer;
 
 #[derive(Debug, Error)]
 pub enum UberServerError {
+    #[error("driver not found: {0}")]
+    DriverNotFound(String),
     #[error("UTF-8 codec error: {0}")]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("driver already exists: {0}")]
+    AlreadyExists(String),
+    #[error("invalid driver: {0}")]
+    InvalidDriver(String),
+    #[error("invalid selector: {0}")]
+    InvalidSelector(String),
+    #[error("invalid log filter: {0}")]
+    InvalidFilter(String),
+    #[error("invalid sandbox profile: {0}")]
+    InvalidSandbox(String),
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("Lua error: {0}")]
@@ -15,30 +43,76 @@ pub enum UberServerError {
     TransportError(#[from] tonic::transport::Error),
 }
 
-pub async fn serve() -> Result<(), UberServerError> {
-    let log_subscriber = crate::logger::init();
+impl From<UberServerError> for tonic::Status {
+    fn from(error: UberServerError) -> Self {
+        match error {
+            UberServerError::DriverNotFound(_) => tonic::Status::not_found(error.to_string()),
+            UberServerError::AlreadyExists(_) => tonic::Status::already_exists(error.to_string()),
+            UberServerError::InvalidDriver(_)
+            | UberServerError::InvalidSelector(_)
+            | UberServerError::InvalidFilter(_)
+            | UberServerError::InvalidSandbox(_) => {
+                tonic::Status::invalid_argument(error.to_string())
+            }
+            UberServerError::PermissionDenied(_) => {
+                tonic::Status::permission_denied(error.to_string())
+            }
+            error => tonic::Status::internal(error.to_string()),
+        }
+    }
+}
+
+pub async fn serve(config: Config) -> Result<(), UberServerError> {
+    // a listener that cannot be secured should stop the server before it starts anything
+    let tls = config.tcp.as_ref().map(TcpConfig::tls_config).transpose()?;
+    let log_subscriber = crate::logger::init(config.log_history);
 
     let local_set = LocalSet::new();
 
     local_set
         .run_until(async move {
-            let incoming = Listener::new()?;
-            let service = Service::new(log_subscriber)?;
+            let incoming = Listener::new(&config.socket)?;
+            let service = DriverServer::new(Service::new(log_subscriber, &config)?);
+
+            log::info!("starting service on {}", config.socket.path.display());
+
+            let unix = tonic::transport::Server::builder()
+                .add_service(service.clone())
+                .serve_with_incoming(incoming);
+
+            match (&config.tcp, tls) {
+                (Some(tcp), Some(tls)) => {
+                    log::info!("starting service on {}", tcp.address);
 
-            log::info!("starting service");
+                    let tcp = tonic::transport::Server::builder()
+                        .tls_config(tls)?
+                        .add_service(service)
+                        .serve(tcp.address);
 
-            tonic::transport::Server::builder()
-                .add_service(DriverServer::new(service))
-                .serve_with_incoming(incoming)
-                .await?;
+                    tokio::try_join!(unix, tcp)?;
+                }
+                _ => unix.await?,
+            }
 
             Ok(())
         })
         .await
 }
 
+mod access;
+mod builtin;
+mod clock;
+mod config;
+mod driver;
+mod events;
+mod exec;
 mod executor;
 mod listener;
 mod logger;
+mod logqueue;
+mod sandbox;
+mod selector;
 mod service;
+mod state;
 mod unixstream;
+mod value;
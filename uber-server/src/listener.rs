@@ -1,6 +1,14 @@
-use crate::unixstream::UnixStream;
+use crate::{config::SocketConfig, unixstream::UnixStream, UberServerError};
 use futures_core::{ready, Stream};
-use std::{path::Path, task::Poll};
+use nix::{
+    sys::stat::{umask, Mode},
+    unistd::{Gid, Group, Uid, User},
+};
+use std::{
+    fs::Permissions,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    task::Poll,
+};
 use tokio::net::UnixListener;
 
 pub struct Listener {
@@ -8,17 +16,80 @@ pub struct Listener {
 }
 
 impl Listener {
-    pub fn new() -> std::io::Result<Self> {
-        let path = Path::new("/tmp/uber-driver.sock");
+    pub fn new(config: &SocketConfig) -> Result<Self, UberServerError> {
+        let path = config.path.as_path();
+
         if path.exists() {
-            let _ = std::fs::remove_file(path)?;
+            // a stale socket from a previous run is replaced, but a live one belongs to another
+            // server
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(UberServerError::InvalidConfig(format!(
+                    "{} is in use by another server",
+                    path.display()
+                )));
+            }
+
+            // the path may be mistyped, so only a socket is ever removed
+            if !std::fs::symlink_metadata(path)?.file_type().is_socket() {
+                return Err(UberServerError::InvalidConfig(format!(
+                    "{} exists and is not a socket",
+                    path.display()
+                )));
+            }
+
+            std::fs::remove_file(path)?;
+        }
+
+        let owner = config.owner.as_deref().map(resolve_user).transpose()?;
+        let group = config.group.as_deref().map(resolve_group).transpose()?;
+
+        // with a configured mode the socket starts out accessible to the server's user only, so
+        // nobody can connect between binding it and applying the mode
+        let inner = match config.mode {
+            Some(_) => {
+                let previous = umask(Mode::from_bits_truncate(0o177));
+                let inner = UnixListener::bind(path);
+                umask(previous);
+
+                inner?
+            }
+            None => UnixListener::bind(path)?,
+        };
+
+        if let Some(mode) = config.mode {
+            std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+
+        if owner.is_some() || group.is_some() {
+            nix::unistd::chown(path, owner, group).map_err(std::io::Error::from)?;
         }
-        let inner = UnixListener::bind(path)?;
 
         Ok(Self { inner })
     }
 }
 
+fn resolve_user(user: &str) -> Result<Uid, UberServerError> {
+    if let Ok(uid) = user.parse() {
+        return Ok(Uid::from_raw(uid));
+    }
+
+    User::from_name(user)
+        .map_err(std::io::Error::from)?
+        .map(|user| user.uid)
+        .ok_or_else(|| UberServerError::InvalidConfig(format!("unknown user: {user}")))
+}
+
+fn resolve_group(group: &str) -> Result<Gid, UberServerError> {
+    if let Ok(gid) = group.parse() {
+        return Ok(Gid::from_raw(gid));
+    }
+
+    Group::from_name(group)
+        .map_err(std::io::Error::from)?
+        .map(|group| group.gid)
+        .ok_or_else(|| UberServerError::InvalidConfig(format!("unknown group: {group}")))
+}
+
 impl Stream for Listener {
     type Item = std::io::Result<UnixStream>;
 
@@ -31,3 +102,48 @@ impl Stream for Listener {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socket_gets_the_configured_mode() {
+        let path = std::env::temp_dir().join(format!("uber-driver-{}.sock", std::process::id()));
+
+        for mode in [0o600, 0o660, 0o666] {
+            let config = SocketConfig {
+                path: path.clone(),
+                mode: Some(mode),
+                ..Default::default()
+            };
+            let listener = Listener::new(&config).unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+
+            assert_eq!(metadata.permissions().mode() & 0o7777, mode);
+            drop(listener);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_files_are_not_replaced() {
+        let path = std::env::temp_dir().join(format!("uber-driver-{}.txt", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+
+        let config = SocketConfig {
+            path: path.clone(),
+            ..Default::default()
+        };
+        let result = Listener::new(&config);
+
+        assert!(
+            matches!(&result, Err(UberServerError::InvalidConfig(message)) if message.contains(&*path.to_string_lossy())),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
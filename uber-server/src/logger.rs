@@ -1,28 +1,219 @@
-use crate::service::LogSender;
-use log::{Metadata, Record, SetLoggerError};
-use uber_protos::LogLevel;
-use std::sync::{Arc, Mutex};
+use crate::{logqueue::LogSender, UberServerError};
+use log::{
+    kv::{self, Key, VisitSource},
+    Metadata, Record, SetLoggerError,
+};
+use regex::Regex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use uber_protos::{LogEventsRequest, LogLevel};
+
+/// Key under which records about a driver carry its identifier.
+pub const DRIVER_ID_KEY: &str = "driver_id";
+
+/// Logs a record about a driver, tagged with its identifier so log subscribers can filter on it.
+macro_rules! driver_log {
+    ($level:ident, $driver_id:expr, $($arg:tt)+) => {{
+        let driver_id: &str = $driver_id;
+
+        log::$level!(driver_id = driver_id; "{driver_id}: {}", format_args!($($arg)+))
+    }};
+}
+pub(crate) use driver_log;
+
+/// Target of the records scripts produce.
+pub const SCRIPT_TARGET: &str = "uber_server::executor";
+
+/// Logs a record a script produced, located at `file` and `line` of its chunk rather than in the
+/// server's source, with the script's own key/value `fields`.
+pub fn script_log(
+    level: log::Level,
+    driver_id: &str,
+    file: Option<&str>,
+    line: Option<u32>,
+    message: &str,
+    fields: &[(String, String)],
+) {
+    if level > log::max_level() {
+        return;
+    }
+
+    let key_values = std::iter::once((DRIVER_ID_KEY, driver_id))
+        .chain(
+            fields
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+        .collect::<Vec<_>>();
+
+    log::logger().log(
+        &Record::builder()
+            .level(level)
+            .target(SCRIPT_TARGET)
+            .file(file)
+            .line(line)
+            .key_values(&key_values.as_slice())
+            .args(format_args!("{driver_id}: {message}"))
+            .build(),
+    );
+}
+
+/// Records kept for replay unless the config says otherwise.
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1000;
 
 pub struct Logger {
-    clients: Arc<Mutex<Vec<LogSender>>>,
+    shared: Arc<Mutex<Shared>>,
     inner: env_logger::Logger,
 }
 
+#[derive(Clone)]
 pub struct LogSubscriber {
-    clients: Arc<Mutex<Vec<LogSender>>>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// Subscribers and history share a lock so that a replay is followed by exactly the records that
+/// came after it.
+struct Shared {
+    clients: Vec<Client>,
+    history: VecDeque<Entry>,
+    capacity: usize,
+    seq: u64,
+}
+
+/// A record as sent to subscribers, along with what filters need to know about it.
+#[derive(Clone)]
+struct Entry {
+    event: uber_protos::LogEvent,
+    level: log::Level,
+    time: SystemTime,
+}
+
+struct Client {
+    filter: LogFilter,
+    sender: LogSender,
+}
+
+/// Decides which records a subscriber is sent.
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    level: Option<log::Level>,
+    targets: Vec<String>,
+    /// Selectors for the drivers whose records are wanted; empty for records about anything.
+    drivers: Vec<String>,
+    /// The identifiers `drivers` resolved to when last refreshed.
+    driver_ids: Vec<String>,
+    patterns: Vec<Regex>,
+    /// Most recent records from the history to send before new ones.
+    replay: usize,
+    /// Only replay records logged at or after this time.
+    since: Option<SystemTime>,
+}
+
+impl LogFilter {
+    /// Builds a filter from a request, using `resolve` to find the drivers its selectors pick.
+    pub fn new(
+        request: &LogEventsRequest,
+        resolve: impl Fn(&[String]) -> Result<Vec<String>, UberServerError>,
+    ) -> Result<Self, UberServerError> {
+        let level = request.level.map(|_| log::Level::from(request.level()));
+        let mut patterns = Vec::new();
+
+        if !request.grep.is_empty() {
+            patterns.push(compile(&request.grep)?);
+        }
+
+        if !request.glob.is_empty() {
+            patterns.push(compile(&glob_to_regex(&request.glob))?);
+        }
+
+        let since = request
+            .since
+            .clone()
+            .and_then(|since| SystemTime::try_from(since).ok());
+        // a start time alone asks for everything kept since then
+        let replay = match (request.replay, since) {
+            (Some(replay), _) => replay as usize,
+            (None, Some(_)) => usize::MAX,
+            (None, None) => 0,
+        };
+
+        Ok(Self {
+            level,
+            targets: request.targets.clone(),
+            drivers: request.drivers.clone(),
+            driver_ids: resolve(&request.drivers)?,
+            patterns,
+            replay,
+            since,
+        })
+    }
+
+    fn accepts(&self, entry: &Entry) -> bool {
+        if self.level.is_some_and(|level| entry.level > level) {
+            return false;
+        }
+
+        let target = entry.event.target.as_str();
+        let nested = |prefix: &String| {
+            target
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        if !self.targets.is_empty() && !self.targets.iter().any(nested) {
+            return false;
+        }
+
+        if !self.drivers.is_empty() && !self.driver_ids.contains(&entry.event.driver_id) {
+            return false;
+        }
+
+        self.patterns
+            .iter()
+            .all(|pattern| pattern.is_match(&entry.event.message))
+    }
 }
 
-pub fn init() -> LogSubscriber {
-    try_init().expect("logger::init should not be called after logger initialied")
+fn compile(pattern: &str) -> Result<Regex, UberServerError> {
+    Regex::new(pattern).map_err(|error| UberServerError::InvalidFilter(error.to_string()))
 }
 
-pub fn try_init() -> Result<LogSubscriber, SetLoggerError> {
-    let clients: Arc<Mutex<Vec<LogSender>>> = Default::default();
+/// Translates a glob matched against a whole message. Wildcards match newlines too, so a glob can
+/// match a message that spans several lines, such as a traceback.
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("(?s)^");
+
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    pattern.push('$');
+    pattern
+}
+
+/// Installs the logger, keeping up to `capacity` recent records for replay.
+pub fn init(capacity: usize) -> LogSubscriber {
+    try_init(capacity).expect("logger::init should not be called after logger initialied")
+}
+
+pub fn try_init(capacity: usize) -> Result<LogSubscriber, SetLoggerError> {
+    let shared = Arc::new(Mutex::new(Shared {
+        clients: Vec::new(),
+        history: VecDeque::with_capacity(capacity),
+        capacity,
+        seq: 0,
+    }));
     let subscriber = LogSubscriber {
-        clients: clients.clone(),
+        shared: shared.clone(),
     };
     let inner = env_logger::Logger::from_default_env();
-    let logger = Logger { clients, inner };
+    let logger = Logger { shared, inner };
 
     log::set_max_level(logger.inner.filter());
     log::set_boxed_logger(Box::new(logger))?;
@@ -31,12 +222,45 @@ pub fn try_init() -> Result<LogSubscriber, SetLoggerError> {
 }
 
 impl LogSubscriber {
-    pub fn push(&mut self, client: LogSender) {
-        log::info!("forwarding log records to {client:?}");
+    /// Sends the subscriber the history its filter asks for, then every new record it accepts.
+    pub fn push(&mut self, filter: LogFilter, sender: LogSender) {
+        log::info!("forwarding log records to {sender:?} with {filter:?}");
+
+        let mut shared = self.shared.lock().unwrap();
+        let mut replay = shared
+            .history
+            .iter()
+            .rev()
+            .filter(|entry| filter.since.is_none_or(|since| entry.time >= since))
+            .filter(|entry| filter.accepts(entry))
+            .take(filter.replay)
+            .collect::<Vec<_>>();
+        replay.reverse();
 
-        let mut clients = self.clients.lock().unwrap();
+        for entry in replay {
+            sender.send(entry.event.clone());
+        }
+
+        shared.clients.push(Client { filter, sender })
+    }
+
+    /// Resolves the driver selectors of every subscriber again, so that drivers started since
+    /// they subscribed are picked up by name or labels too.
+    pub fn refresh(&mut self, resolve: impl Fn(&[String]) -> Result<Vec<String>, UberServerError>) {
+        let mut shared = self.shared.lock().unwrap();
+
+        // subscribers whose stream has been dropped are pruned as a side effect
+        shared.clients.retain(|client| !client.sender.is_closed());
 
-        clients.push(client)
+        for client in shared.clients.iter_mut() {
+            if client.filter.drivers.is_empty() {
+                continue;
+            }
+
+            if let Ok(driver_ids) = resolve(&client.filter.drivers) {
+                client.filter.driver_ids = driver_ids;
+            }
+        }
     }
 }
 
@@ -47,19 +271,54 @@ impl log::Log for Logger {
 
     fn log(&self, record: &Record<'_>) {
         let level = record.level();
-        let target = record.target().to_string();
-        let args = record.args();
-        let message = format!("{args}");
+        let time = SystemTime::now();
+        let driver_id = record
+            .key_values()
+            .get(Key::from_str(DRIVER_ID_KEY))
+            .and_then(|driver_id| driver_id.to_borrowed_str().map(str::to_string))
+            .unwrap_or_default();
+        let mut message = record.args().to_string();
+
+        // the identifier has a field of its own, so drop the prefix driver_log! adds for humans
+        if !driver_id.is_empty() {
+            if let Some(rest) = message.strip_prefix(&format!("{driver_id}: ")) {
+                message = rest.to_string();
+            }
+        }
+
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
         {
-            let record = uber_protos::LogEvent {
-                level: LogLevel::from(level) as i32,
-                target: target.clone(),
-                message: message.clone(),
+            let mut shared = self.shared.lock().unwrap();
+
+            shared.seq += 1;
+
+            let entry = Entry {
+                event: uber_protos::LogEvent {
+                    level: LogLevel::from(level) as i32,
+                    target: record.target().to_string(),
+                    message,
+                    timestamp: Some(time.into()),
+                    seq: shared.seq,
+                    driver_id,
+                    file: record.file().unwrap_or_default().to_string(),
+                    line: record.line(),
+                    fields: fields.0,
+                },
+                level,
+                time,
             };
-            let clients = self.clients.lock().unwrap();
 
-            for client in clients.iter() {
-                let _ = client.send(Ok(record.clone()));
+            // clients that went away or fell too far behind are dropped, which ends their stream
+            shared.clients.retain(|client| {
+                !client.filter.accepts(&entry) || client.sender.send(entry.event.clone())
+            });
+
+            if shared.capacity > 0 {
+                if shared.history.len() == shared.capacity {
+                    shared.history.pop_front();
+                }
+                shared.history.push_back(entry);
             }
         }
 
@@ -70,3 +329,33 @@ impl log::Log for Logger {
         self.inner.flush()
     }
 }
+
+/// Collects the key/value pairs of a record, other than the driver identifier, as strings.
+#[derive(Default)]
+struct Fields(HashMap<String, String>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        if key.as_str() != DRIVER_ID_KEY {
+            self.0.insert(key.to_string(), value.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_across_lines() {
+        let message = "script failed\nstack traceback:\n\t[C]: in ?";
+        let matches = |glob: &str| compile(&glob_to_regex(glob)).unwrap().is_match(message);
+
+        assert!(matches("*traceback*"));
+        assert!(matches("script failed?stack*"));
+        assert!(!matches("traceback*"));
+        assert!(!matches("*failed"));
+    }
+}
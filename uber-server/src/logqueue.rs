@@ -0,0 +1,250 @@
+use futures_core::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::SystemTime,
+};
+use uber_protos::{
+    driver_event::Event, DriverEvent, EventsDropped, LogEvent, LogLevel, LogOverflow,
+};
+
+/// Records a subscriber may fall behind unless the config says otherwise.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+pub type LogSender = QueueSender<LogEvent>;
+
+/// A record streamed to clients through a bounded queue.
+pub trait Record {
+    /// Stands in for `dropped` records a client was too slow to receive.
+    fn dropped_marker(dropped: u64) -> Self;
+}
+
+/// Creates the queue between the server and one `LogEvents` or `DriverEvents` stream. At most
+/// `capacity` records wait for the client; what happens to more depends on `overflow`.
+pub fn channel<T: Record>(
+    capacity: usize,
+    overflow: LogOverflow,
+) -> (QueueSender<T>, QueueStream<T>) {
+    let queue = Arc::new(Mutex::new(Queue {
+        events: VecDeque::new(),
+        dropped: 0,
+        waker: None,
+        receiver_closed: false,
+        sender_closed: false,
+    }));
+    let sender = QueueSender {
+        queue: queue.clone(),
+        capacity: capacity.max(1),
+        overflow,
+    };
+
+    (sender, QueueStream { queue })
+}
+
+struct Queue<T> {
+    events: VecDeque<Result<T, tonic::Status>>,
+    /// Records dropped since the client last received one.
+    dropped: u64,
+    waker: Option<Waker>,
+    receiver_closed: bool,
+    sender_closed: bool,
+}
+
+impl<T> Queue<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct QueueSender<T> {
+    queue: Arc<Mutex<Queue<T>>>,
+    capacity: usize,
+    overflow: LogOverflow,
+}
+
+impl<T> QueueSender<T> {
+    /// Queues a record for the client. Returns `false` once the client is gone or has been
+    /// disconnected, after which the sender should be dropped.
+    pub fn send(&self, event: T) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+
+        if queue.receiver_closed || queue.sender_closed {
+            return false;
+        }
+
+        if queue.events.len() >= self.capacity {
+            match self.overflow {
+                LogOverflow::DropOldest => {
+                    queue.events.pop_front();
+                    queue.dropped += 1;
+                }
+                LogOverflow::Disconnect => {
+                    queue
+                        .events
+                        .push_back(Err(tonic::Status::resource_exhausted(format!(
+                            "fell more than {} log records behind",
+                            self.capacity
+                        ))));
+                    queue.sender_closed = true;
+                    queue.wake();
+
+                    return false;
+                }
+            }
+        }
+
+        queue.events.push_back(Ok(event));
+        queue.wake();
+
+        true
+    }
+
+    /// Ends the stream with an error once the client has received what is already queued.
+    pub fn fail(&self, status: tonic::Status) {
+        let mut queue = self.queue.lock().unwrap();
+
+        queue.events.push_back(Err(status));
+        queue.sender_closed = true;
+        queue.wake();
+    }
+
+    /// Whether the client has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.queue.lock().unwrap().receiver_closed
+    }
+}
+
+impl<T> fmt::Debug for QueueSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueSender")
+            .field("capacity", &self.capacity)
+            .field("overflow", &self.overflow)
+            .finish()
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+
+        queue.sender_closed = true;
+        queue.wake();
+    }
+}
+
+pub struct QueueStream<T> {
+    queue: Arc<Mutex<Queue<T>>>,
+}
+
+impl<T: Record> Stream for QueueStream<T> {
+    type Item = Result<T, tonic::Status>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.lock().unwrap();
+
+        // the dropped records were older than any still queued, so the marker comes first
+        if queue.dropped > 0 {
+            let dropped = std::mem::take(&mut queue.dropped);
+
+            return Poll::Ready(Some(Ok(T::dropped_marker(dropped))));
+        }
+
+        if let Some(event) = queue.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        if queue.sender_closed {
+            return Poll::Ready(None);
+        }
+
+        queue.waker = Some(cx.waker().clone());
+
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for QueueStream<T> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap();
+
+        queue.receiver_closed = true;
+        queue.events.clear();
+    }
+}
+
+/// The marker has no sequence number of its own; the gap in the numbers of the records around it
+/// shows which ones are missing.
+impl Record for LogEvent {
+    fn dropped_marker(dropped: u64) -> Self {
+        LogEvent {
+            level: LogLevel::Warn as i32,
+            target: module_path!().to_string(),
+            message: format!("{dropped} events dropped"),
+            timestamp: Some(SystemTime::now().into()),
+            seq: 0,
+            driver_id: String::new(),
+            file: String::new(),
+            line: None,
+            fields: HashMap::from([("dropped".to_string(), dropped.to_string())]),
+        }
+    }
+}
+
+impl Record for DriverEvent {
+    fn dropped_marker(dropped: u64) -> Self {
+        DriverEvent {
+            driver_id: String::new(),
+            timestamp: Some(SystemTime::now().into()),
+            event: Some(Event::Dropped(EventsDropped { count: dropped })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+    use uber_protos::DriverKilled;
+
+    fn killed(driver_id: &str) -> DriverEvent {
+        DriverEvent {
+            driver_id: driver_id.to_string(),
+            timestamp: None,
+            event: Some(Event::Killed(DriverKilled {})),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_lose_the_oldest_events() {
+        let (sender, mut stream) = channel(4, LogOverflow::DropOldest);
+
+        for i in 0..100 {
+            assert!(sender.send(killed(&format!("driver-{i}"))));
+        }
+        drop(sender);
+
+        let mut events = Vec::new();
+        while let Some(event) = stream.next().await {
+            events.push(event.unwrap());
+        }
+
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[0].event,
+            Some(Event::Dropped(EventsDropped { count: 96 }))
+        );
+        let driver_ids = events[1..]
+            .iter()
+            .map(|event| event.driver_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            driver_ids,
+            ["driver-96", "driver-97", "driver-98", "driver-99"]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use crate::UberServerError;
+use std::str::FromStr;
+
+/// Globals every profile may use, including the builtins defined by the executor prelude.
+const MINIMAL_GLOBALS: &[&str] = &[
+    "_VERSION",
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "next",
+    "noop",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setmetatable",
+    "sleep",
+    "tonumber",
+    "tostring",
+    "type",
+    "xpcall",
+];
+
+/// Libraries every profile may use.
+const MINIMAL_LIBRARIES: &[&str] = &["math", "string", "table", "utf8"];
+
+/// Libraries that read the host clock.
+const STANDARD_LIBRARIES: &[&str] = &["time"];
+
+/// The side-effect free subset of the `os` library.
+const STANDARD_OS: &[&str] = &["clock", "date", "difftime", "time"];
+
+/// The set of capabilities a driver's environment is built from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Pure computation plus the cooperative builtins.
+    Minimal,
+    /// `Minimal` plus clock access and builtins that talk to the host.
+    #[default]
+    Standard,
+    /// The full global table of the server's Lua state.
+    Trusted,
+}
+
+impl FromStr for Profile {
+    type Err = UberServerError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "minimal" => Ok(Profile::Minimal),
+            "" | "standard" => Ok(Profile::Standard),
+            "trusted" => Ok(Profile::Trusted),
+            _ => Err(UberServerError::InvalidSandbox(value.to_string())),
+        }
+    }
+}
+
+/// Hides the string metatable so scripts cannot reach the library shared by every driver.
+pub fn protect_shared_state(lua: &mlua::Lua) -> mlua::Result<()> {
+    lua.load(mlua::chunk! {
+        getmetatable("").__metatable = false
+    })
+    .exec()
+}
+
+/// Builds a fresh global environment for one driver. `builtins` names the async builtins, which
+/// reach outside of the Lua state and so are left out of the minimal profile.
+pub fn environment<'lua, 'a>(
+    lua: &'lua mlua::Lua,
+    profile: Profile,
+    builtins: impl IntoIterator<Item = &'a str>,
+) -> mlua::Result<mlua::Table<'lua>> {
+    let globals = lua.globals();
+    let env = lua.create_table()?;
+
+    if profile == Profile::Trusted {
+        let metatable = lua.create_table()?;
+        metatable.set("__index", globals)?;
+        env.set_metatable(Some(metatable));
+
+        return Ok(env);
+    }
+
+    copy_fields(&globals, &env, MINIMAL_GLOBALS)?;
+
+    copy_libraries(lua, &globals, &env, MINIMAL_LIBRARIES)?;
+
+    if profile == Profile::Standard {
+        for name in builtins {
+            env.set(name, globals.get::<_, mlua::Value>(name)?)?;
+        }
+
+        copy_libraries(lua, &globals, &env, STANDARD_LIBRARIES)?;
+
+        let os = lua.create_table()?;
+        copy_fields(&globals.get::<_, mlua::Table>("os")?, &os, STANDARD_OS)?;
+        env.set("os", os)?;
+    }
+
+    env.set("_G", env.clone())?;
+
+    Ok(env)
+}
+
+/// Copies each library into a table of its own so drivers cannot modify the shared one.
+fn copy_libraries(
+    lua: &mlua::Lua,
+    from: &mlua::Table,
+    to: &mlua::Table,
+    names: &[&str],
+) -> mlua::Result<()> {
+    for name in names {
+        let library = from.get::<_, mlua::Table>(*name)?;
+        let copy = lua.create_table()?;
+
+        for pair in library.pairs::<mlua::Value, mlua::Value>() {
+            let (key, value) = pair?;
+            copy.set(key, value)?;
+        }
+
+        to.set(*name, copy)?;
+    }
+
+    Ok(())
+}
+
+fn copy_fields(from: &mlua::Table, to: &mlua::Table, names: &[&str]) -> mlua::Result<()> {
+    for name in names {
+        to.set(*name, from.get::<_, mlua::Value>(*name)?)?;
+    }
+
+    Ok(())
+}
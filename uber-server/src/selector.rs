@@ -0,0 +1,93 @@
+use crate::{driver::Driver, UberServerError};
+use std::str::FromStr;
+
+/// Picks drivers by identifier, name or labels, written as `ID`, `NAME` or a comma separated list
+/// of `key=value` and `key!=value` requirements such as `app=web,tier!=canary`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selector {
+    /// A driver identifier or name.
+    Driver(String),
+    /// Requirements every label of a matching driver must meet.
+    Labels(Vec<Requirement>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Requirement {
+    key: String,
+    value: String,
+    equal: bool,
+}
+
+impl Selector {
+    pub fn matches(&self, driver: &Driver) -> bool {
+        match self {
+            Selector::Driver(driver_id) => {
+                driver.driver_id == *driver_id || driver.name == *driver_id
+            }
+            Selector::Labels(requirements) => requirements.iter().all(|requirement| {
+                let value = driver.labels.get(&requirement.key);
+
+                (value == Some(&requirement.value)) == requirement.equal
+            }),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = UberServerError;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        if !selector.contains('=') {
+            return Ok(Selector::Driver(selector.to_string()));
+        }
+
+        let invalid = || UberServerError::InvalidSelector(selector.to_string());
+
+        selector
+            .split(',')
+            .map(|requirement| {
+                let (key, value, equal) = match requirement.split_once("!=") {
+                    Some((key, value)) => (key, value, false),
+                    None => {
+                        let (key, value) = requirement.split_once('=').ok_or_else(invalid)?;
+
+                        (key, value, true)
+                    }
+                };
+
+                validate_label(key, value).map_err(|_| invalid())?;
+
+                Ok(Requirement {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    equal,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Selector::Labels)
+    }
+}
+
+/// Checks that a driver identifier or name cannot be mistaken for a label selector.
+pub fn validate_name(name: &str) -> Result<(), UberServerError> {
+    if name.is_empty() || name.contains('=') {
+        return Err(UberServerError::InvalidDriver(format!(
+            "names and identifiers must be non-empty and must not contain '=': {name:?}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks that a label can be written in a selector.
+pub fn validate_label(key: &str, value: &str) -> Result<(), UberServerError> {
+    let valid = |text: &str| !text.contains(['=', ',', '!']);
+
+    if key.is_empty() || !valid(key) || !valid(value) {
+        return Err(UberServerError::InvalidDriver(format!(
+            "label keys must be non-empty and labels must not contain '=', ',' or '!': {key}={value}"
+        )));
+    }
+
+    Ok(())
+}
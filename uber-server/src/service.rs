@@ -1,70 +1,202 @@
-use crate::{executor::Executor, logger::LogSubscriber, UberServerError};
+use crate::{
+    access::{AccessPolicy, Caller, Permission},
+    executor::Executor,
+    logger::{LogFilter, LogSubscriber},
+    logqueue, Config, UberServerError,
+};
 use futures_core::Stream;
 use std::pin::Pin;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, oneshot};
 use uber_protos::{
-    driver_server::Driver, DriverResponse, EchoRequest, EchoResponse, LogEvent, StartDriverRequest,
+    driver_server::Driver, DriverEvent, DriverEventsRequest, DriverInfo, DriverResponse,
+    EchoRequest, EchoResponse, GetDriverRequest, ListDriversRequest, ListDriversResponse, LogEvent,
+    LogEventsRequest, LogLevel, LogOverflow, SetLogLevelRequest, StartDriverRequest,
     StopDriverRequest,
 };
 
-pub type LogSender = mpsc::UnboundedSender<Result<LogEvent, tonic::Status>>;
+const REQUEST_CHANNEL_CAPACITY: usize = 32;
+
+/// Log target for the record of who called what.
+const AUDIT_TARGET: &str = "uber_server::audit";
+
+pub type EventSender = logqueue::QueueSender<DriverEvent>;
+
+type ResponseSender<T = DriverResponse> = oneshot::Sender<Result<T, tonic::Status>>;
 
 pub struct Service {
     request_tx: mpsc::Sender<ExecutorRequest>,
-    response_rx: Mutex<mpsc::Receiver<DriverResponse>>,
+    access: Option<AccessPolicy>,
+    log_queue: usize,
 }
 
 #[derive(Debug)]
 enum ExecutorRequest {
-    Log(LogSender),
-    Start(StartDriverRequest),
-    Stop(StopDriverRequest),
+    Log(LogEventsRequest, logqueue::LogSender),
+    Events(DriverEventsRequest, EventSender),
+    Start(StartDriverRequest, ResponseSender),
+    Stop(StopDriverRequest, ResponseSender),
+    SetLogLevel(SetLogLevelRequest, ResponseSender),
+    List(ListDriversRequest, ResponseSender<ListDriversResponse>),
+    Get(GetDriverRequest, ResponseSender<DriverInfo>),
 }
 
 impl Service {
-    pub fn new(mut log_subscriber: LogSubscriber) -> Result<Self, UberServerError> {
-        let (request_tx, mut request_rx) = mpsc::channel(1);
-        let (response_tx, response_rx) = mpsc::channel(1);
-        let response_rx = Mutex::new(response_rx);
-        let mut executor = Executor::new()?;
+    pub fn new(
+        mut log_subscriber: LogSubscriber,
+        config: &Config,
+    ) -> Result<Self, UberServerError> {
+        let (request_tx, mut request_rx) = mpsc::channel(REQUEST_CHANNEL_CAPACITY);
+        let mut executor = Executor::new(config)?;
+
+        executor.restore()?;
 
         tokio::task::spawn_local(async move {
             while let Some(request) = request_rx.recv().await {
-                let response_tx = response_tx.clone();
-                let response = match request {
-                    ExecutorRequest::Log(log_tx) => {
-                        log_subscriber.push(log_tx);
+                match request {
+                    ExecutorRequest::Log(request, log_tx) => {
+                        let filter = LogFilter::new(&request, |selectors| {
+                            executor.resolve_drivers(selectors)
+                        });
+
+                        match filter {
+                            Ok(filter) => log_subscriber.push(filter, log_tx),
+                            Err(error) => log_tx.fail(error.into()),
+                        }
+                    }
+                    ExecutorRequest::Events(request, event_tx) => {
+                        executor.subscribe_events(request, event_tx)
+                    }
+                    ExecutorRequest::Start(request, response_tx) => {
+                        let driver_id = request.driver_id.clone();
+                        let result = executor.create_coroutine(request);
+
+                        if result.is_ok() {
+                            log_subscriber.refresh(|selectors| executor.resolve_drivers(selectors));
+                        }
+
+                        // a rejected start gets its own status, such as ALREADY_EXISTS or
+                        // PERMISSION_DENIED, so callers can tell the reasons apart
+                        let response = result
+                            .map(|()| DriverResponse {
+                                driver_id,
+                                error: None,
+                                driver_ids: Vec::new(),
+                            })
+                            .map_err(tonic::Status::from);
+
+                        // the caller may have gone away, in which case nobody wants the response
+                        let _ = response_tx.send(response);
+                    }
+                    ExecutorRequest::Stop(
+                        StopDriverRequest {
+                            driver_id,
+                            grace_period,
+                        },
+                        response_tx,
+                    ) => {
+                        let grace_period =
+                            grace_period.and_then(|duration| duration.try_into().ok());
+                        let response = executor
+                            .stop_coroutine(&driver_id, grace_period)
+                            .map(|driver_ids| DriverResponse {
+                                driver_id,
+                                error: None,
+                                driver_ids,
+                            })
+                            .map_err(tonic::Status::from);
 
-                        None
+                        let _ = response_tx.send(response);
                     }
-                    ExecutorRequest::Start(StartDriverRequest { driver_id, payload }) => {
-                        let error = match executor.create_coroutine(driver_id.clone(), payload) {
-                            Ok(()) => None,
-                            Err(error) => Some(error.to_string()),
+                    ExecutorRequest::SetLogLevel(
+                        SetLogLevelRequest { driver_id, level },
+                        response_tx,
+                    ) => {
+                        let response = match LogLevel::from_i32(level) {
+                            Some(level) => executor
+                                .set_log_level(
+                                    &driver_id,
+                                    log::Level::from(level).to_level_filter(),
+                                )
+                                .map(|driver_ids| DriverResponse {
+                                    driver_id,
+                                    error: None,
+                                    driver_ids,
+                                })
+                                .map_err(tonic::Status::from),
+                            None => Err(tonic::Status::invalid_argument(format!(
+                                "invalid log level: {level}"
+                            ))),
                         };
 
-                        Some(DriverResponse { driver_id, error })
+                        let _ = response_tx.send(response);
                     }
-                    ExecutorRequest::Stop(StopDriverRequest { driver_id }) => {
-                        Some(executor.kill_coroutine(driver_id))
+                    ExecutorRequest::List(ListDriversRequest { selector }, response_tx) => {
+                        let selector =
+                            Some(selector.as_str()).filter(|selector| !selector.is_empty());
+                        let response = executor
+                            .list_drivers(selector)
+                            .map(|drivers| ListDriversResponse { drivers })
+                            .map_err(tonic::Status::from);
+
+                        let _ = response_tx.send(response);
                     }
-                };
+                    ExecutorRequest::Get(GetDriverRequest { driver_id, wait }, response_tx) => {
+                        match executor.get_driver(&driver_id, wait) {
+                            Ok(info_rx) => {
+                                // a waiting caller is answered when the driver terminates, so
+                                // the executor moves on to other requests in the meantime
+                                tokio::task::spawn_local(async move {
+                                    let response = info_rx.await.map_err(|_| {
+                                        tonic::Status::aborted(format!(
+                                            "driver {driver_id} was replaced"
+                                        ))
+                                    });
 
-                if let Some(response) = response {
-                    response_tx
-                        .send(response)
-                        .await
-                        .expect("service channel dropped");
+                                    let _ = response_tx.send(response);
+                                });
+                            }
+                            Err(error) => {
+                                let _ = response_tx.send(Err(error.into()));
+                            }
+                        }
+                    }
                 }
             }
         });
 
         Ok(Self {
             request_tx,
-            response_rx,
+            access: config.access.clone(),
+            log_queue: config.log_queue,
         })
     }
 
+    /// Checks the caller against the access policy and records the decision in the audit log.
+    fn authorize<T>(
+        &self,
+        request: &tonic::Request<T>,
+        permission: Permission,
+        action: &str,
+    ) -> Result<(), UberServerError> {
+        let caller = Caller::from_request(request);
+        let allowed = self
+            .access
+            .as_ref()
+            .is_none_or(|policy| policy.allows(&caller, permission));
+
+        if allowed {
+            log::info!(target: AUDIT_TARGET, "{caller}: {action}");
+
+            Ok(())
+        } else {
+            log::warn!(target: AUDIT_TARGET, "{caller}: {action} denied");
+
+            Err(UberServerError::PermissionDenied(format!(
+                "{caller} may not {permission}"
+            )))
+        }
+    }
+
     async fn send(&self, request: ExecutorRequest) -> Result<(), tonic::Status> {
         self.request_tx
             .send(request)
@@ -72,19 +204,17 @@ impl Service {
             .map_err(|error| tonic::Status::internal(error.to_string()))
     }
 
-    async fn execute(
+    async fn execute<T>(
         &self,
-        request: ExecutorRequest,
-    ) -> Result<tonic::Response<DriverResponse>, tonic::Status> {
-        self.send(request).await?;
+        request: impl FnOnce(ResponseSender<T>) -> ExecutorRequest,
+    ) -> Result<tonic::Response<T>, tonic::Status> {
+        let (response_tx, response_rx) = oneshot::channel();
 
-        let response = self
-            .response_rx
-            .lock()
-            .await
-            .recv()
+        self.send(request(response_tx)).await?;
+
+        let response = response_rx
             .await
-            .ok_or_else(|| tonic::Status::internal("connection dropped"))?;
+            .map_err(|_| tonic::Status::internal("connection dropped"))??;
 
         Ok(tonic::Response::new(response))
     }
@@ -93,39 +223,108 @@ impl Service {
 #[tonic::async_trait]
 impl Driver for Service {
     type LogEventsStream = Pin<Box<dyn Stream<Item = Result<LogEvent, tonic::Status>> + Send>>;
+    type DriverEventsStream =
+        Pin<Box<dyn Stream<Item = Result<DriverEvent, tonic::Status>> + Send>>;
 
     async fn start_driver(
         &self,
         request: tonic::Request<StartDriverRequest>,
     ) -> Result<tonic::Response<DriverResponse>, tonic::Status> {
+        let action = format!("start_driver {}", request.get_ref().driver_id);
+        self.authorize(&request, Permission::Start, &action)?;
+
         let request = request.into_inner();
 
         log::info!("start_driver {request:?}");
 
-        self.execute(ExecutorRequest::Start(request)).await
+        self.execute(|tx| ExecutorRequest::Start(request, tx)).await
     }
 
     async fn stop_driver(
         &self,
         request: tonic::Request<StopDriverRequest>,
     ) -> Result<tonic::Response<DriverResponse>, tonic::Status> {
+        let action = format!("stop_driver {}", request.get_ref().driver_id);
+        self.authorize(&request, Permission::Stop, &action)?;
+
         let request = request.into_inner();
 
         log::info!("stop_driver {request:?}");
 
-        self.execute(ExecutorRequest::Stop(request)).await
+        self.execute(|tx| ExecutorRequest::Stop(request, tx)).await
+    }
+
+    async fn set_log_level(
+        &self,
+        request: tonic::Request<SetLogLevelRequest>,
+    ) -> Result<tonic::Response<DriverResponse>, tonic::Status> {
+        let action = format!("set_log_level {}", request.get_ref().driver_id);
+        self.authorize(&request, Permission::Stop, &action)?;
+
+        let request = request.into_inner();
+
+        log::info!("set_log_level {request:?}");
+
+        self.execute(|tx| ExecutorRequest::SetLogLevel(request, tx))
+            .await
+    }
+
+    async fn list_drivers(
+        &self,
+        request: tonic::Request<ListDriversRequest>,
+    ) -> Result<tonic::Response<ListDriversResponse>, tonic::Status> {
+        self.authorize(&request, Permission::Observe, "list_drivers")?;
+
+        let request = request.into_inner();
+
+        log::info!("list_drivers {request:?}");
+
+        self.execute(|tx| ExecutorRequest::List(request, tx)).await
+    }
+
+    async fn get_driver(
+        &self,
+        request: tonic::Request<GetDriverRequest>,
+    ) -> Result<tonic::Response<DriverInfo>, tonic::Status> {
+        let action = format!("get_driver {}", request.get_ref().driver_id);
+        self.authorize(&request, Permission::Observe, &action)?;
+
+        let request = request.into_inner();
+
+        log::info!("get_driver {request:?}");
+
+        self.execute(|tx| ExecutorRequest::Get(request, tx)).await
     }
 
     async fn log_events(
         &self,
-        _request: tonic::Request<()>,
+        request: tonic::Request<LogEventsRequest>,
     ) -> Result<tonic::Response<Self::LogEventsStream>, tonic::Status> {
-        let (tx, rx) = mpsc::unbounded_channel();
-        let rx = tokio_stream::wrappers::UnboundedReceiverStream::new(rx);
+        self.authorize(&request, Permission::Observe, "log_events")?;
+
+        let request = request.into_inner();
+        let (tx, rx) = logqueue::channel(self.log_queue, request.overflow());
+
+        log::info!("log events stream {request:?}");
+
+        self.send(ExecutorRequest::Log(request, tx)).await?;
+
+        Ok(tonic::Response::new(Box::pin(rx)))
+    }
+
+    async fn driver_events(
+        &self,
+        request: tonic::Request<DriverEventsRequest>,
+    ) -> Result<tonic::Response<Self::DriverEventsStream>, tonic::Status> {
+        self.authorize(&request, Permission::Observe, "driver_events")?;
+
+        let request = request.into_inner();
+        // events are never worth disconnecting over; a slow client is told how many it missed
+        let (tx, rx) = logqueue::channel(self.log_queue, LogOverflow::DropOldest);
 
-        log::info!("log events stream");
+        log::info!("driver events stream {request:?}");
 
-        self.send(ExecutorRequest::Log(tx)).await?;
+        self.send(ExecutorRequest::Events(request, tx)).await?;
 
         Ok(tonic::Response::new(Box::pin(rx)))
     }
@@ -142,3 +341,136 @@ impl Driver for Service {
         Ok(tonic::Response::new(response))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{rc::Rc, sync::OnceLock};
+    use tokio::task::LocalSet;
+
+    /// The logger can only be installed once per process, so tests share its subscriber.
+    fn log_subscriber() -> LogSubscriber {
+        static SUBSCRIBER: OnceLock<LogSubscriber> = OnceLock::new();
+
+        SUBSCRIBER.get_or_init(|| crate::logger::init(0)).clone()
+    }
+
+    async fn start(service: &Service, driver_id: String) -> (String, DriverResponse) {
+        let request = StartDriverRequest {
+            driver_id: driver_id.clone(),
+            payload: b"sleep(60)".to_vec(),
+            ..Default::default()
+        };
+        let response = service
+            .start_driver(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+
+        (driver_id, response)
+    }
+
+    async fn stop(service: &Service, driver_id: String) -> (String, DriverResponse) {
+        let request = StopDriverRequest {
+            driver_id: driver_id.clone(),
+            grace_period: None,
+        };
+        let response = service
+            .stop_driver(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+
+        (driver_id, response)
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_get_their_own_responses() {
+        LocalSet::new()
+            .run_until(async {
+                let service = Rc::new(Service::new(log_subscriber(), &Config::default()).unwrap());
+
+                let starts = (0..64)
+                    .map(|i| {
+                        let service = service.clone();
+
+                        tokio::task::spawn_local(async move {
+                            start(&service, format!("driver-{i}")).await
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                for start in starts {
+                    let (driver_id, response) = start.await.unwrap();
+
+                    assert_eq!(response.driver_id, driver_id);
+                    assert_eq!(response.error, None);
+                }
+
+                // stops of the first half race with starts of a second batch
+                let mixed = (0..32)
+                    .map(|i| {
+                        let service = service.clone();
+
+                        tokio::task::spawn_local(async move {
+                            let (driver_id, response) = stop(&service, format!("driver-{i}")).await;
+
+                            assert_eq!(response.driver_ids, vec![driver_id.clone()]);
+
+                            (driver_id, response)
+                        })
+                    })
+                    .chain((64..96).map(|i| {
+                        let service = service.clone();
+
+                        tokio::task::spawn_local(async move {
+                            start(&service, format!("driver-{i}")).await
+                        })
+                    }))
+                    .collect::<Vec<_>>();
+
+                for request in mixed {
+                    let (driver_id, response) = request.await.unwrap();
+
+                    assert_eq!(response.driver_id, driver_id);
+                    assert_eq!(response.error, None);
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn rejected_starts_fail_with_a_status() {
+        LocalSet::new()
+            .run_until(async {
+                let service = Service::new(log_subscriber(), &Config::default()).unwrap();
+                let requests = [
+                    (
+                        StartDriverRequest {
+                            driver_id: "trusted".to_string(),
+                            sandbox: "trusted".to_string(),
+                            ..Default::default()
+                        },
+                        tonic::Code::PermissionDenied,
+                    ),
+                    (
+                        StartDriverRequest {
+                            driver_id: "a=b".to_string(),
+                            ..Default::default()
+                        },
+                        tonic::Code::InvalidArgument,
+                    ),
+                ];
+
+                for (request, code) in requests {
+                    let status = service
+                        .start_driver(tonic::Request::new(request))
+                        .await
+                        .unwrap_err();
+
+                    assert_eq!(status.code(), code, "{status}");
+                }
+            })
+            .await;
+    }
+}
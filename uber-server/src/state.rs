@@ -0,0 +1,83 @@
+use prost::Message;
+use std::{
+    fmt::Write,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use uber_protos::StartDriverRequest;
+
+const RECORD_EXTENSION: &str = "driver";
+
+/// A directory holding the start request of every driver that should be launched when the server
+/// starts, one file per driver.
+#[derive(Debug)]
+pub struct StateDir {
+    path: PathBuf,
+}
+
+impl StateDir {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(path)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Records a driver, replacing any earlier record with the same id.
+    pub fn save(&self, request: &StartDriverRequest) -> std::io::Result<()> {
+        let path = self.record_path(&request.driver_id);
+        let temporary = path.with_extension("tmp");
+
+        // write a new file and rename it over the old one so a crash never leaves half a record
+        std::fs::write(&temporary, request.encode_to_vec())?;
+        std::fs::rename(&temporary, &path)
+    }
+
+    pub fn remove(&self, driver_id: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.record_path(driver_id)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads every record, skipping and logging those that cannot be decoded.
+    pub fn load(&self) -> std::io::Result<Vec<StartDriverRequest>> {
+        let mut requests = Vec::new();
+
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+
+            if path.extension().and_then(|extension| extension.to_str()) != Some(RECORD_EXTENSION) {
+                continue;
+            }
+
+            match StartDriverRequest::decode(std::fs::read(&path)?.as_slice()) {
+                Ok(request) => requests.push(request),
+                Err(error) => log::error!("{}: {error}", path.display()),
+            }
+        }
+
+        Ok(requests)
+    }
+
+    /// Driver ids are chosen by clients, so anything that is not safe in a file name is escaped.
+    fn record_path(&self, driver_id: &str) -> PathBuf {
+        let mut name = String::with_capacity(driver_id.len());
+
+        for (index, byte) in driver_id.bytes().enumerate() {
+            let safe = byte.is_ascii_alphanumeric()
+                || byte == b'-'
+                || byte == b'_'
+                || (byte == b'.' && index > 0);
+
+            if safe {
+                name.push(byte as char);
+            } else {
+                let _ = write!(name, "%{byte:02X}");
+            }
+        }
+
+        self.path.join(format!("{name}.{RECORD_EXTENSION}"))
+    }
+}
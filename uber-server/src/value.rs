@@ -0,0 +1,181 @@
+use prost_types::{value::Kind, ListValue, Struct, Value};
+use std::collections::BTreeMap;
+
+/// Nested tables deeper than this are cut off.
+const MAX_DEPTH: usize = 32;
+
+/// Values converted from a single Lua value before the rest is cut off, so that tables which
+/// share subtables cannot expand into an enormous tree.
+const MAX_NODES: usize = 10_000;
+
+pub fn to_proto(value: &mlua::Value) -> Value {
+    Converter::default().convert(value)
+}
+
+pub fn to_proto_multi(values: &mlua::MultiValue) -> Vec<Value> {
+    values.iter().map(to_proto).collect()
+}
+
+/// Converts a protobuf value into Lua, turning whole numbers into integers.
+pub fn from_proto<'lua>(lua: &'lua mlua::Lua, value: &Value) -> mlua::Result<mlua::Value<'lua>> {
+    let value = match &value.kind {
+        None | Some(Kind::NullValue(_)) => mlua::Value::Nil,
+        Some(Kind::BoolValue(value)) => mlua::Value::Boolean(*value),
+        Some(Kind::NumberValue(value)) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => {
+            mlua::Value::Integer(*value as i64)
+        }
+        Some(Kind::NumberValue(value)) => mlua::Value::Number(*value),
+        Some(Kind::StringValue(value)) => mlua::Value::String(lua.create_string(value)?),
+        Some(Kind::ListValue(list)) => mlua::Value::Table(
+            lua.create_sequence_from(
+                list.values
+                    .iter()
+                    .map(|value| from_proto(lua, value))
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )?,
+        ),
+        Some(Kind::StructValue(object)) => mlua::Value::Table(struct_from_proto(lua, object)?),
+    };
+
+    Ok(value)
+}
+
+pub fn struct_from_proto<'lua>(
+    lua: &'lua mlua::Lua,
+    object: &Struct,
+) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+
+    for (key, value) in &object.fields {
+        table.raw_set(key.as_str(), from_proto(lua, value)?)?;
+    }
+
+    Ok(table)
+}
+
+/// Converts one Lua value, keeping track of the tables being expanded to spot reference cycles
+/// and of how many values were produced so far.
+#[derive(Default)]
+struct Converter<'lua> {
+    ancestors: Vec<mlua::Table<'lua>>,
+    nodes: usize,
+}
+
+impl<'lua> Converter<'lua> {
+    fn convert(&mut self, value: &mlua::Value<'lua>) -> Value {
+        self.nodes += 1;
+
+        let kind = match value {
+            _ if self.nodes > MAX_NODES => Kind::StringValue("<truncated>".to_string()),
+            mlua::Value::Nil => Kind::NullValue(0),
+            mlua::Value::Boolean(value) => Kind::BoolValue(*value),
+            mlua::Value::Integer(value) => Kind::NumberValue(*value as f64),
+            mlua::Value::Number(value) => Kind::NumberValue(*value),
+            mlua::Value::String(value) => Kind::StringValue(value.to_string_lossy().into_owned()),
+            mlua::Value::Table(table) if self.ancestors.contains(table) => {
+                Kind::StringValue("<cycle>".to_string())
+            }
+            mlua::Value::Table(table) if self.ancestors.len() < MAX_DEPTH => {
+                self.ancestors.push(table.clone());
+                let kind = self.table(table);
+                self.ancestors.pop();
+
+                kind
+            }
+            mlua::Value::Error(error) => Kind::StringValue(error.to_string()),
+            value => Kind::StringValue(format!("<{}>", value.type_name())),
+        };
+
+        Value { kind: Some(kind) }
+    }
+
+    fn table(&mut self, table: &mlua::Table<'lua>) -> Kind {
+        let len = table.raw_len() as usize;
+        let mut entries = BTreeMap::new();
+        let mut count = 0;
+
+        for pair in table.clone().pairs::<mlua::Value, mlua::Value>() {
+            if self.nodes > MAX_NODES {
+                break;
+            }
+
+            let (key, value) = match pair {
+                Ok(pair) => pair,
+                Err(_) => continue,
+            };
+            let key = match key {
+                mlua::Value::String(key) => key.to_string_lossy().into_owned(),
+                mlua::Value::Integer(key) => key.to_string(),
+                mlua::Value::Number(key) => key.to_string(),
+                mlua::Value::Boolean(key) => key.to_string(),
+                key => format!("<{}>", key.type_name()),
+            };
+
+            count += 1;
+            entries.insert(key, self.convert(&value));
+        }
+
+        if len > 0 && count == len {
+            let values = (1..=len)
+                .map(|index| {
+                    entries
+                        .remove(&index.to_string())
+                        .unwrap_or(Value { kind: None })
+                })
+                .collect();
+
+            Kind::ListValue(ListValue { values })
+        } else {
+            Kind::StructValue(Struct {
+                fields: entries.into_iter().collect(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(source: &str) -> Value {
+        let lua = mlua::Lua::new();
+        let value = lua.load(source).eval::<mlua::Value>().unwrap();
+
+        to_proto(&value)
+    }
+
+    #[test]
+    fn cycles_become_placeholders() {
+        let value = convert("local t = { name = 'root' } t.self = t return t");
+        let Some(Kind::StructValue(object)) = value.kind else {
+            panic!("expected a struct: {value:?}");
+        };
+
+        assert_eq!(
+            object.fields["self"].kind,
+            Some(Kind::StringValue("<cycle>".to_string()))
+        );
+    }
+
+    fn count(value: &Value) -> usize {
+        1 + match &value.kind {
+            Some(Kind::ListValue(list)) => list.values.iter().map(count).sum(),
+            Some(Kind::StructValue(object)) => object.fields.values().map(count).sum(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn self_referencing_lists_are_not_expanded() {
+        let value = convert("local t = {} for i = 1, 4 do t[i] = t end return t");
+
+        assert_eq!(count(&value), 5);
+    }
+
+    #[test]
+    fn shared_subtables_are_capped() {
+        let value = convert("local t = {} for d = 1, 32 do t = { t, t, t, t } end return t");
+
+        assert!(count(&value) <= MAX_NODES + MAX_DEPTH, "{}", count(&value));
+    }
+}
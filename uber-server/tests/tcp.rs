@@ -0,0 +1,113 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    time::Duration,
+};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use uber_protos::{driver_client::DriverClient, ListDriversRequest};
+use uber_server::{Config, SocketConfig, TcpConfig, UberServerError};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn read_fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture(name)).unwrap()
+}
+
+fn tcp_config(address: SocketAddr, client_ca: Option<PathBuf>) -> TcpConfig {
+    TcpConfig {
+        address,
+        cert: fixture("server.pem"),
+        key: fixture("server.key"),
+        client_ca,
+        allow_anonymous: false,
+    }
+}
+
+fn unused_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+async fn list_drivers(address: SocketAddr, identity: bool) -> Result<(), String> {
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(read_fixture("ca.pem")))
+        .domain_name("localhost");
+    if identity {
+        tls = tls.identity(Identity::from_pem(
+            read_fixture("client.pem"),
+            read_fixture("client.key"),
+        ));
+    }
+
+    let channel = Channel::from_shared(format!("https://{address}"))
+        .unwrap()
+        .tls_config(tls)
+        .map_err(|error| error.to_string())?
+        .connect()
+        .await
+        .map_err(|error| error.to_string())?;
+
+    DriverClient::new(channel)
+        .list_drivers(ListDriversRequest::default())
+        .await
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+#[tokio::test]
+async fn tcp_listener_needs_client_ca_or_explicit_opt_in() {
+    let mut tcp = tcp_config(unused_address(), None);
+    let config = Config {
+        tcp: Some(tcp.clone()),
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        uber_server::serve(config).await,
+        Err(UberServerError::InvalidConfig(_))
+    ));
+
+    tcp.allow_anonymous = true;
+    assert!(tcp.tls_config().is_ok());
+}
+
+#[tokio::test]
+async fn tcp_clients_must_present_a_certificate() {
+    let address = unused_address();
+    let config = move || Config {
+        socket: SocketConfig {
+            path: std::env::temp_dir().join(format!("uber-driver-tcp-{}.sock", std::process::id())),
+            ..Default::default()
+        },
+        tcp: Some(tcp_config(address, Some(fixture("ca.pem")))),
+        ..Default::default()
+    };
+
+    // the server and its config stay on one thread, so it gets a runtime of its own
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(uber_server::serve(config()))
+            .unwrap();
+    });
+
+    let mut result = Err(String::new());
+    for _ in 0..100 {
+        result = list_drivers(address, true).await;
+        if result.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    assert_eq!(result, Ok(()));
+    assert!(list_drivers(address, false).await.is_err());
+}